@@ -0,0 +1,171 @@
+use ggez::graphics::{Color, Rect};
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+use crate::pendulum::{cell_inside, cell_rect, cells_adjacent, Config, DoublePendulum, Motion};
+
+/// all the cells of a family, struct-of-arrays. the id of a cell is its index,
+/// cells are never removed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Arena {
+    pub motion:    Vec<Motion>,
+    // fixed point, pixels
+    pub p:         Vec<DVec2>,
+    pub scale:     Vec<f64>,
+    pub color:     Vec<Color>,
+    pub parent:    Vec<usize>,
+    pub childs:    Vec<Vec<usize>>,
+    pub neighbors: Vec<Vec<usize>>,
+    // half of the sides of every cell at scale 1, pixels
+    size:          DVec2,
+}
+
+impl Arena {
+    pub fn new(dims: DVec2) -> Self {
+        Arena {
+            motion:    Vec::new(),
+            p:         Vec::new(),
+            scale:     Vec::new(),
+            color:     Vec::new(),
+            parent:    Vec::new(),
+            childs:    Vec::new(),
+            neighbors: Vec::new(),
+            size:      dims / 2.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.motion.len()
+    }
+
+    /// store `p` as a child of `parent` (the root is its own parent), returns its id
+    pub fn insert(&mut self, p: DoublePendulum, parent: Option<usize>) -> usize {
+        let id = self.len();
+        self.motion.push(p.motion);
+        self.p.push(p.p);
+        self.scale.push(p.scale);
+        self.color.push(p.color);
+        self.parent.push(parent.unwrap_or(id));
+        self.childs.push(Vec::new());
+        self.neighbors.push(p.neighbors);
+        if let Some(parent) = parent {
+            self.childs[parent].push(id);
+        }
+        id
+    }
+
+    /// copy of the cell `id`
+    pub fn get(&self, id: usize) -> DoublePendulum {
+        let mut p = DoublePendulum::new(self.p[id], 0.0, 0.0, self.scale[id]);
+        p.id = id;
+        p.parent_id = self.parent[id];
+        p.childs = self.childs[id].clone();
+        p.neighbors = self.neighbors[id].clone();
+        p.size = self.size;
+        p.motion = self.motion[id];
+        p.color = self.color[id];
+        p
+    }
+
+    /// the up to four quarters of the stopped cell `id` that lie inside the window, none below `min_pixel`
+    pub fn split(&self, id: usize, config: &Config) -> Vec<DoublePendulum> {
+        assert!(self.motion[id].stopped);
+        if self.width(id) < config.min_pixel {
+            return vec![];
+        }
+        let (p, d, dims) = (self.p[id], self.half(id) / 2.0, self.size * 2.0);
+        let mut res = Vec::new();
+        for (dx, dy) in [(-d.x, -d.y), (-d.x, d.y), (d.x, -d.y), (d.x, d.y)] {
+            let c = dvec2(p.x + dx, p.y + dy);
+            if c.x > 0.0 && c.x < dims.x && c.y > 0.0 && c.y < dims.y {
+                let mut child = DoublePendulum::new2(c, dims, self.scale[id] / 2.0, config);
+                child.color = self.color[id];
+                res.push(child);
+            }
+        }
+        res
+    }
+
+    pub fn half(&self, id: usize) -> DVec2 {
+        self.size * self.scale[id]
+    }
+
+    /// half of the shorter side of the cell, pixels
+    pub fn width(&self, id: usize) -> f64 {
+        self.half(id).min_element()
+    }
+
+    pub fn rect(&self, id: usize) -> Rect {
+        cell_rect(self.p[id], self.half(id))
+    }
+
+    pub fn point_inside(&self, id: usize, x: f64, y: f64) -> bool {
+        cell_inside(self.p[id], self.half(id), x, y)
+    }
+
+    pub fn adjacent(&self, a: usize, b: usize) -> bool {
+        cells_adjacent((self.p[a], self.half(a)), (self.p[b], self.half(b)))
+    }
+
+    /// the physics of the cells `ids`, which must be sorted and unique, for updating in parallel
+    pub fn motions_mut(&mut self, ids: &[usize]) -> Vec<&mut Motion> {
+        let mut res = Vec::with_capacity(ids.len());
+        let mut rest = &mut self.motion[..];
+        let mut start = 0;
+        for &id in ids {
+            let (m, tail) = rest[id - start..].split_first_mut().expect("ids are sorted and in the arena");
+            res.push(m);
+            rest = tail;
+            start = id + 1;
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::*;
+
+    use super::Arena;
+    use crate::pendulum::{Config, DoublePendulum};
+
+    #[test]
+    fn test_insert() {
+        let config = Config::default();
+        let dims = dvec2(400.0, 200.0);
+        let mut cells = Arena::new(dims);
+        let mut root = DoublePendulum::new2(dims / 2.0, dims, 1.0, &config);
+        root.motion.stopped = true;
+        let root = cells.insert(root, None);
+        let childs = cells.split(root, &Config { min_pixel: 4.0, ..config.clone() });
+        let ids = childs.into_iter().map(|c| cells.insert(c, Some(root))).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        assert_eq!(cells.childs[root], ids);
+        assert_eq!(cells.parent[3], root);
+
+        let c = cells.get(3);
+        assert_eq!((c.id, c.parent_id), (3, root));
+        assert_eq!((c.p, c.scale), (cells.p[3], cells.scale[3]));
+        assert_eq!(c.state(), DoublePendulum::new2(c.p, dims, 0.5, &config).state());
+        assert!(cells.adjacent(1, 2) && cells.adjacent(1, 3));
+        assert!(cells.get(0).motion.stopped);
+        cells.motion[1].stopped = true;
+        assert!(cells.split(1, &Config { min_pixel: 100.0, ..config.clone() }).is_empty());
+        assert_eq!(cells.split(1, &config).len(), 4);
+    }
+
+    #[test]
+    fn test_motions_mut() {
+        let config = Config::default();
+        let dims = dvec2(100.0, 100.0);
+        let mut cells = Arena::new(dims);
+        for _ in 0..5 {
+            cells.insert(DoublePendulum::new2(dims / 2.0, dims, 1.0, &config), None);
+        }
+        for m in cells.motions_mut(&[1, 2, 4]) {
+            m.steps = 7;
+        }
+        let steps = cells.motion.iter().map(|m| m.steps).collect::<Vec<_>>();
+        assert_eq!(steps, vec![0, 7, 7, 0, 7]);
+    }
+}
@@ -1,10 +1,11 @@
 use std::collections::VecDeque;
 use std::ops::*;
-use std::time::{Instant, Duration};
+
+use serde::{Deserialize, Serialize};
 
 
 /// moving (rolling) average
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RollingAverage<T> {
     hist: VecDeque<T>,
     sum: T,
@@ -20,7 +21,7 @@ where T: AddAssign + SubAssign + Div + Add
     pub fn new(size: usize) -> Self {
         let this = RollingAverage {
             hist: VecDeque::with_capacity(size),
-            sum: (0 as u32).into(),
+            sum: 0_u32.into(),
             size,
         };
         // info!("xxx {:?}", this);
@@ -37,7 +38,7 @@ where T: AddAssign + SubAssign + Div + Add
     }
 
     pub fn get(&self) -> T {
-        if self.hist.len() == 0 {
+        if self.hist.is_empty() {
             return 0.into()
         }
         self.sum / (self.hist.len() as u32).into()
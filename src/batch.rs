@@ -0,0 +1,238 @@
+use std::f64::consts::PI;
+
+use wide::{f64x4, CmpGe, CmpGt, CmpLt};
+
+use crate::pendulum::{flip_fraction, FlipInterp, Motion, STEP_DELTA};
+
+/// pendulums stepped together
+pub const LANES: usize = 4;
+
+/// the batch agrees with the scalar `SemiImplicitEuler` path to this (absolute, radians and rad/s) over
+/// 1000 steps of a regular pendulum. the vector sin and cos are a few ulp off the scalar ones, in chaotic
+/// cells that grows like any other rounding error, so single flip times there can differ
+#[cfg_attr(not(test), allow(dead_code))]
+pub const TOLERANCE: f64 = 1e-9;
+
+/// the batch flip times agree with the scalar ones to this, seconds, for cells that flip in the same step. it is the
+/// state error of TOLERANCE moving the interpolated moment inside the last step, far below a step (STEP_DELTA)
+#[cfg_attr(not(test), allow(dead_code))]
+pub const FLIP_TOLERANCE: f64 = 1e-9;
+
+/// what stops a pendulum of the batch besides max_step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    /// nothing, it runs through the flips. the grid of p2 runs so
+    RunThrough,
+    /// the first flip of the second arm, as `FlipPolicy` with one flip, the moment inside the step estimated by
+    /// the `FlipInterp`. the fractal cells keep the scalar path, which repeats the baseline to the bit, so only
+    /// the tests stop here
+    #[cfg_attr(not(test), allow(dead_code))]
+    AtFlip(FlipInterp),
+}
+
+/// pendulums in the lanes of SIMD registers
+struct Lanes {
+    theta1: f64x4,
+    theta2: f64x4,
+    dt1:    f64x4,
+    dt2:    f64x4,
+    l1:     f64x4,
+    l2:     f64x4,
+    m1:     f64x4,
+    m2:     f64x4,
+    g:      f64x4,
+    // `Equations::sign`
+    sign:   f64x4,
+    t:      f64x4,
+    h:      f64x4,
+    prev:   f64x4,
+}
+
+impl Lanes {
+    fn load(ms: &[Motion]) -> Self {
+        // unused lanes repeat the first pendulum, they are never stored back
+        let lane = |f: fn(&Motion) -> f64| f64x4::from(std::array::from_fn(|i| f(ms.get(i).unwrap_or(&ms[0]))));
+        Lanes {
+            theta1: lane(|m| m.state.theta1),
+            theta2: lane(|m| m.state.theta2),
+            dt1:    lane(|m| m.state.dt1),
+            dt2:    lane(|m| m.state.dt2),
+            l1:     lane(|m| m.params.l1),
+            l2:     lane(|m| m.params.l2),
+            m1:     lane(|m| m.params.m1),
+            m2:     lane(|m| m.params.m2),
+            g:      lane(|m| m.params.g),
+            sign:   lane(|m| m.params.equations.sign()),
+            t:      lane(|m| m.t),
+            h:      lane(|m| m.h),
+            prev:   lane(|m| m.prev),
+        }
+    }
+
+    /// angular accelerations, the same expression as `physics::accel`
+    #[inline(always)]
+    fn accel(&self) -> (f64x4, f64x4) {
+        let two = f64x4::splat(2.0);
+        let (m1, m2, g, l1, l2, sign) = (self.m1, self.m2, self.g, self.l1, self.l2, self.sign);
+        let (t1, t2, dt1, dt2) = (self.theta1, self.theta2, self.dt1, self.dt2);
+        let (s1, c1) = t1.sin_cos();
+        let (s12, c12) = (t1 - t2).sin_cos();
+        let a = two * m1 + m2 - m2 * (two * t1 - two * t2).cos();
+
+        let d2t1 = (-g * (two * m1 + m2) * s1
+            - m2 * g * (t1 - two * t2).sin()
+            - two * s12 * m2 * (dt2 * dt2 * l2 + sign * dt1 * dt1 * l1 * c12))
+            / (l1 * a);
+
+        let d2t2 = (two * s12 * (dt1 * dt1 * l1 * (m1 + m2) + g * (m1 + m2) * c1 + dt2 * dt2 * l2 * m2 * c12)) / (l2 * a);
+
+        (d2t1, d2t2)
+    }
+}
+
+/// advance up to LANES pendulums by `update_steps` steps of the semi-implicit euler, the same as
+/// `Motion::update` with `SemiImplicitEuler`, stopping them as `stop` says
+pub fn update(ms: &mut [Motion], update_steps: usize, max_step: usize, stop: Stop) {
+    assert!(!ms.is_empty() && ms.len() <= LANES);
+    let mut l = Lanes::load(ms);
+    let mut stopped: [bool; LANES] = std::array::from_fn(|i| ms.get(i).is_none_or(|m| m.stopped));
+    let (pi, zero) = (f64x4::splat(PI), f64x4::splat(0.0));
+    let max_ticks = f64x4::splat(max_step as f64);
+
+    for _ in 1..=update_steps {
+        // out of time: stop before the step, like `Motion::step`
+        let expire = (l.t / f64x4::splat(STEP_DELTA)).round().cmp_ge(max_ticks).to_array();
+        for (i, m) in ms.iter_mut().enumerate() {
+            if !stopped[i] && expire[i] != 0.0 {
+                stopped[i] = true;
+                (m.stopped, m.expired, m.flip_t) = (true, true, l.t.to_array()[i]);
+            }
+        }
+        if stopped.iter().all(|s| *s) {
+            break;
+        }
+        let run = f64x4::from(stopped.map(|s| if s { 0.0 } else { f64::from_bits(u64::MAX) }));
+
+        let prev_dt2 = l.dt2;
+        let (d2t1, d2t2) = l.accel();
+        let dt1 = l.dt1 + d2t1 * l.h;
+        let dt2 = l.dt2 + d2t2 * l.h;
+        l.theta1 = run.blend(l.theta1 + dt1 * l.h, l.theta1);
+        l.theta2 = run.blend(l.theta2 + dt2 * l.h, l.theta2);
+        l.dt1 = run.blend(dt1, l.dt1);
+        l.dt2 = run.blend(dt2, l.dt2);
+        l.t = run.blend(l.t + l.h, l.t);
+        for (i, m) in ms.iter_mut().enumerate() {
+            m.steps += !stopped[i] as usize;
+        }
+
+        if let Stop::AtFlip(interp) = stop {
+            // `FlipPolicy::classic`
+            let (th2, prev, dt2) = (l.theta2, l.prev, l.dt2);
+            let (down, up) = (th2.cmp_lt(-pi) & prev.cmp_gt(-pi), th2.cmp_gt(pi) & prev.cmp_lt(pi));
+            let (back_down, back_up) = (th2.cmp_lt(pi) & prev.cmp_gt(pi), th2.cmp_gt(-pi) & prev.cmp_lt(-pi));
+            let flip = run
+                & prev.is_finite()
+                & ((dt2.cmp_gt(zero) & (down | up | back_up)) | (dt2.cmp_lt(zero) & (down | up | back_down)));
+            if flip.any() {
+                let flip = flip.to_array();
+                let (th2, prev, dt2, prev_dt2, t, h) = (
+                    th2.to_array(),
+                    prev.to_array(),
+                    dt2.to_array(),
+                    prev_dt2.to_array(),
+                    l.t.to_array(),
+                    l.h.to_array(),
+                );
+                for (i, m) in ms.iter_mut().enumerate() {
+                    if flip[i] == 0.0 {
+                        continue;
+                    }
+                    stopped[i] = true;
+                    m.stopped = true;
+                    let level = if th2[i] < -PI && prev[i] > -PI || th2[i] > -PI && prev[i] < -PI { -PI } else { PI };
+                    let s = flip_fraction(interp, level, (prev[i], prev_dt2[i]), (th2[i], dt2[i]), h[i]);
+                    m.flip_t = t[i] - (1.0 - s) * h[i];
+                }
+            }
+        }
+
+        l.prev = run.blend(l.theta2, l.prev);
+    }
+
+    let (theta1, theta2, dt1, dt2) = (l.theta1.to_array(), l.theta2.to_array(), l.dt1.to_array(), l.dt2.to_array());
+    let (t, prev) = (l.t.to_array(), l.prev.to_array());
+    for (i, m) in ms.iter_mut().enumerate() {
+        (m.state.theta1, m.state.theta2, m.state.dt1, m.state.dt2) = (theta1[i], theta2[i], dt1[i], dt2[i]);
+        (m.t, m.prev) = (t[i], prev[i]);
+        // as in the scalar step of the grid: NaN is a broken pendulum, once there it stays
+        let s = m.state;
+        assert!(![s.theta1, s.theta2, s.dt1, s.dt2].iter().any(|v| v.is_nan()), "{:?} {:?} t={}", m.params, s, m.t);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::*;
+
+    use super::{update, Stop, FLIP_TOLERANCE, LANES, TOLERANCE};
+    use crate::pendulum::{Config, DoublePendulum, FlipInterp, Motion, STEP_DELTA};
+    use crate::physics::{SemiImplicitEuler, State};
+    use crate::policy::FlipPolicy;
+
+    fn motion(x: f64, y: f64) -> Motion {
+        let dims = dvec2(100.0, 100.0);
+        DoublePendulum::new2(dvec2(x, y), dims, 1.0, &Config::default()).motion
+    }
+
+    #[test]
+    fn test_matches_scalar() {
+        // small swings around the bottom
+        let ms = [motion(1.0, 2.0), motion(2.0, 1.0), motion(1.5, 1.5), motion(3.0, 3.0)];
+        let mut batch = ms;
+        update(&mut batch, 1000, usize::MAX, Stop::RunThrough);
+        for (m, b) in ms.iter().zip(&batch) {
+            let mut m = *m;
+            m.update(1000, usize::MAX, &SemiImplicitEuler, &FlipPolicy::new(&Config::default()));
+            assert_eq!((m.steps, m.t), (b.steps, b.t));
+            let d = [m.state.theta1 - b.state.theta1, m.state.theta2 - b.state.theta2, m.state.dt1 - b.state.dt1];
+            assert!(d.iter().all(|d| d.abs() < TOLERANCE), "{:?}", d);
+            assert!((m.state.dt2 - b.state.dt2).abs() < TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn test_flip() {
+        // theta2 starts near PI: flips fast. the last lane is unused
+        let ms = [motion(10.0, 99.0), motion(20.0, 98.0), motion(50.0, 99.5)];
+        assert!(ms.len() < LANES);
+        // agreeing to the tolerance is agreeing on the moment inside the step, not only on the step
+        const { assert!(FLIP_TOLERANCE < STEP_DELTA * 1e-6) };
+        let mut batch = ms;
+        update(&mut batch, 5000, 4000, Stop::AtFlip(FlipInterp::Hermite));
+        let policy = FlipPolicy::new(&Config { flip_interp: FlipInterp::Hermite, ..Default::default() });
+        for (m, b) in ms.iter().zip(&batch) {
+            let mut m = *m;
+            m.update(5000, 4000, &SemiImplicitEuler, &policy);
+            assert!(m.stopped && b.stopped);
+            assert_eq!((m.expired, m.steps), (b.expired, b.steps));
+            assert!((m.flip_t - b.flip_t).abs() < FLIP_TOLERANCE, "{} {}", m.flip_t, b.flip_t);
+        }
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut batch = [motion(1.0, 1.0)];
+        update(&mut batch, 100, 30, Stop::AtFlip(FlipInterp::Hermite));
+        assert!(batch[0].stopped && batch[0].expired);
+        assert_eq!(batch[0].steps, 30);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_nan() {
+        let m = motion(1.0, 1.0);
+        let mut batch = [m, Motion { state: State { dt2: f64::NAN, ..m.state }, ..m }];
+        update(&mut batch, 10, usize::MAX, Stop::RunThrough);
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::pendulum::PendulumFamily;
+
+/// bump this when the layout of `PendulumFamily` changes, old checkpoints are refused
+pub const CHECKPOINT_VERSION: u32 = 7;
+
+const MAGIC: [u8; 8] = *b"dpfrac\0\0";
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(PathBuf, std::io::Error),
+    Format(PathBuf, String),
+    Version(PathBuf, u32),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            CheckpointError::Format(path, e) => write!(f, "{}: not a checkpoint: {}", path.display(), e),
+            CheckpointError::Version(path, v) => write!(
+                f,
+                "{}: checkpoint version {} is not supported ({}), start the render again",
+                path.display(),
+                v,
+                CHECKPOINT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    magic:   [u8; 8],
+    version: u32,
+}
+
+/// write the whole family to `path`. goes through a temporary file, so a crash while saving keeps
+/// the previous checkpoint
+pub fn save(path: &Path, family: &PendulumFamily) -> Result<(), CheckpointError> {
+    let io = |e| CheckpointError::Io(path.into(), e);
+    let format = |e: bincode::Error| CheckpointError::Format(path.into(), e.to_string());
+    let tmp = path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&tmp).map_err(io)?);
+    bincode::serialize_into(&mut w, &Header { magic: MAGIC, version: CHECKPOINT_VERSION }).map_err(format)?;
+    bincode::serialize_into(&mut w, family).map_err(format)?;
+    w.flush().map_err(io)?;
+    drop(w);
+    fs::rename(&tmp, path).map_err(io)
+}
+
+/// the family saved in `path`, ready to continue where it stopped
+pub fn load(path: &Path) -> Result<PendulumFamily, CheckpointError> {
+    let format = |e: bincode::Error| CheckpointError::Format(path.into(), e.to_string());
+    let mut r = BufReader::new(File::open(path).map_err(|e| CheckpointError::Io(path.into(), e))?);
+    let header: Header = bincode::deserialize_from(&mut r).map_err(format)?;
+    if header.magic != MAGIC {
+        return Err(CheckpointError::Format(path.into(), "bad magic".into()));
+    }
+    if header.version != CHECKPOINT_VERSION {
+        return Err(CheckpointError::Version(path.into(), header.version));
+    }
+    let mut family: PendulumFamily = bincode::deserialize_from(&mut r).map_err(format)?;
+    family.restore();
+    Ok(family)
+}
+
+/// saves a checkpoint every `every`, and when asked to
+pub struct Autosave {
+    path:  PathBuf,
+    // None: only when asked to
+    every: Option<Duration>,
+    last:  Instant,
+}
+
+impl Autosave {
+    pub fn new(path: PathBuf, every: Option<Duration>) -> Self {
+        Autosave { path, every, last: Instant::now() }
+    }
+
+    /// save if the time has come
+    pub fn tick(&mut self, family: &PendulumFamily) {
+        if self.every.is_some_and(|every| self.last.elapsed() >= every) {
+            self.save(family);
+        }
+    }
+
+    /// save now. a failed save is reported, the render goes on
+    pub fn save(&mut self, family: &PendulumFamily) {
+        let t = Instant::now();
+        match save(&self.path, family) {
+            Ok(()) => println!("checkpoint: {} saved in {:?}", self.path.display(), t.elapsed()),
+            Err(e) => eprintln!("checkpoint: {}", e),
+        }
+        self.last = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+
+    use glam::*;
+
+    use super::{load, save, CheckpointError, CHECKPOINT_VERSION, MAGIC};
+    use crate::pendulum::{run_all, small_config, PendulumFamily};
+
+    #[test]
+    fn test_resume() {
+        let mut family = PendulumFamily::new(small_config(), dvec2(128.0, 128.0));
+        family.init();
+        for _ in 0..5 {
+            family.update().unwrap();
+        }
+        assert!(family.len() > 0);
+        let path = temp_dir().join(format!("dpfrac-test-{}.checkpoint", std::process::id()));
+        save(&path, &family).unwrap();
+        let mut resumed = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((resumed.iter, resumed.update_steps, &resumed.ps), (family.iter, family.update_steps, &family.ps));
+
+        run_all(&mut family);
+        run_all(&mut resumed);
+        assert_eq!(family.cells.len(), resumed.cells.len());
+        assert_eq!(family.cells.neighbors, resumed.cells.neighbors);
+        for (a, b) in family.cells.motion.iter().zip(&resumed.cells.motion) {
+            assert_eq!((a.flip_t, a.steps, a.expired), (b.flip_t, b.steps, b.expired));
+        }
+    }
+
+    #[test]
+    fn test_version() {
+        let path = temp_dir().join(format!("dpfrac-test-v0-{}.checkpoint", std::process::id()));
+        let header = bincode::serialize(&(MAGIC, CHECKPOINT_VERSION + 1)).unwrap();
+        std::fs::write(&path, header).unwrap();
+        let res = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(res, Err(CheckpointError::Version(_, v)) if v == CHECKPOINT_VERSION + 1));
+    }
+}
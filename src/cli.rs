@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use glam::*;
+
+use crate::config::{self, ConfigError, Validate, PRESETS};
+use crate::pendulum::{FlipInterp, Symmetry};
+use crate::physics::{Equations, IntegratorKind, Quantity};
+use crate::policy::{ColorBy, PolicyKind};
+use crate::{p2, pendulum, quadtree};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Mode {
+    /// adaptive flip-time fractal: x, y => angles of the arms
+    Fractal,
+    /// experimental fixed grid (p2): x, y => lengths of the arms
+    Grid,
+}
+
+/// Double pendulum fractal
+///
+/// The config is taken from --config or --preset, then the options given on
+/// the command line override single fields of it
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// what to render
+    #[arg(long, value_enum, default_value_t = Mode::Fractal)]
+    pub mode: Mode,
+
+    /// width of the window (or the image) in pixels. the one of a field given to --config by default
+    #[arg(long, default_value_t = 2048.0)]
+    pub width: f64,
+
+    /// height of the window (or the image) in pixels. the one of a field given to --config by default
+    #[arg(long, default_value_t = 2048.0)]
+    pub height: f64,
+
+    /// do not render, write the poincare section of the pendulum at these values of the x and y axis
+    /// quantities to --poincare-csv and exit. in the window, P shows the one of the clicked cell
+    #[arg(long, num_args = 2, value_names = ["X", "Y"], allow_negative_numbers = true)]
+    pub poincare: Option<Vec<f64>>,
+
+    /// where the poincare section is saved, C saves it in the window
+    #[arg(long, value_name = "CSV", default_value = "poincare.csv")]
+    pub poincare_csv: PathBuf,
+
+    /// seconds of motion the poincare section is collected over
+    #[arg(long, default_value_t = 50_000.0)]
+    pub poincare_time: f64,
+
+    /// do not open a window, render to the end and save the image to this png
+    #[arg(long, value_name = "PNG")]
+    pub headless: Option<PathBuf>,
+
+    /// when the render ends, also save the flip times (seconds, float32) to this .npy, with the config
+    /// next to it in a .json
+    #[arg(long, value_name = "NPY")]
+    pub npy: Option<PathBuf>,
+
+    /// when the render ends, also save every cell of the quadtree (center, size, depth, parent, flip) to
+    /// this .csv or .json
+    #[arg(long, value_name = "FILE", value_parser = quadtree::check_path)]
+    pub cells: Option<PathBuf>,
+
+    /// load the config from a .toml or .json file
+    #[arg(long, value_name = "FILE", conflicts_with = "preset")]
+    pub config: Option<PathBuf>,
+
+    /// start from a built-in config, --mode fractal only
+    #[arg(long, value_parser = PRESETS)]
+    pub preset: Option<String>,
+
+    /// save the resulting config to a .toml or .json file
+    #[arg(long, value_name = "FILE")]
+    pub save_config: Option<PathBuf>,
+
+    /// where the render is saved, with the S key and every --checkpoint-minutes
+    #[arg(long, value_name = "FILE", default_value = "dpfrac.checkpoint")]
+    pub checkpoint: PathBuf,
+
+    /// save the render this often, 0 for only with the S key
+    #[arg(long, value_name = "MINUTES", default_value_t = 10.0)]
+    pub checkpoint_minutes: f64,
+
+    /// continue the render saved in --checkpoint, with its config and size
+    #[arg(long, conflicts_with_all = ["config", "preset", "save_config"])]
+    pub resume: bool,
+
+    /// quantity that changes along x
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().x_axis)]
+    pub x_axis: Quantity,
+
+    /// quantity that changes along y
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().y_axis)]
+    pub y_axis: Quantity,
+
+    /// value of the x quantity at the left edge
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().region.xmin)]
+    pub xmin: f64,
+
+    /// value of the x quantity at the right edge
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().region.xmax)]
+    pub xmax: f64,
+
+    /// value of the y quantity at the top edge
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().region.ymin)]
+    pub ymin: f64,
+
+    /// value of the y quantity at the bottom edge
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().region.ymax)]
+    pub ymax: f64,
+
+    /// initial angle of the first arm, unless it is on an axis
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().theta1)]
+    pub theta1: f64,
+
+    /// initial angle of the second arm, unless it is on an axis
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().theta2)]
+    pub theta2: f64,
+
+    /// initial angular velocity of the first arm, unless it is on an axis
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().dt1)]
+    pub dt1: f64,
+
+    /// initial angular velocity of the second arm, unless it is on an axis
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().dt2)]
+    pub dt2: f64,
+
+    /// length of the first arm, unless it is on an axis. a quarter of --width by default
+    #[arg(long)]
+    pub l1: Option<f64>,
+
+    /// length of the second arm, unless it is on an axis. a quarter of --width by default
+    #[arg(long)]
+    pub l2: Option<f64>,
+
+    /// hue shift per step of flip time; play with it together with color-mod
+    #[arg(long, default_value_t = pendulum::Config::default().color_step)]
+    pub color_step: f64,
+
+    /// flip time (in steps * color-step) after which the hue wraps around
+    #[arg(long, default_value_t = pendulum::Config::default().color_mod)]
+    pub color_mod: usize,
+
+    /// split two adjacent cells if the ratio of their flip times is below this. 0..1
+    #[arg(long, default_value_t = pendulum::Config::default().dive_diff)]
+    pub dive_diff: f64,
+
+    /// time (in steps of 0.01s) before a pendulum that did not flip is expired (drawn gray)
+    #[arg(long, default_value_t = pendulum::Config::default().max_step)]
+    pub max_step: usize,
+
+    /// do not split cells smaller than this many pixels
+    #[arg(long, default_value_t = pendulum::Config::default().min_pixel)]
+    pub min_pixel: f64,
+
+    /// physics steps per frame grow as exp(exp(iter / speed-a) / speed-b)
+    #[arg(long, default_value_t = pendulum::Config::default().speed_a)]
+    pub speed_a: f64,
+
+    /// see speed-a
+    #[arg(long, default_value_t = pendulum::Config::default().speed_b)]
+    pub speed_b: f64,
+
+    /// numerical integrator for the physics
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().integrator)]
+    pub integrator: IntegratorKind,
+
+    /// equations of motion, lagrange corrects the sign of one term of the baseline ones
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().equations)]
+    pub equations: Equations,
+
+    /// absolute error tolerance per step of the adaptive integrator (rk45)
+    #[arg(long, default_value_t = pendulum::Config::default().atol)]
+    pub atol: f64,
+
+    /// relative error tolerance per step of the adaptive integrator (rk45)
+    #[arg(long, default_value_t = pendulum::Config::default().rtol)]
+    pub rtol: f64,
+
+    /// what stops a cell and how it is colored
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().policy)]
+    pub policy: PolicyKind,
+
+    /// stop a cell at this flip of the watched arms instead of the first
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        default_value_t = pendulum::Config::default().flip_count
+    )]
+    pub flip_count: u32,
+
+    /// with --policy lyapunov: seconds every cell runs for
+    #[arg(long, default_value_t = pendulum::Config::default().lyap_time)]
+    pub lyap_time: f64,
+
+    /// with --policy lyapunov: the exponent (1/s) colored brightest, smaller ones go to dark blue
+    #[arg(long, default_value_t = pendulum::Config::default().lyap_scale)]
+    pub lyap_scale: f64,
+
+    /// color the stopped cells by the time of the flip, by which arm flipped and which way, or by the
+    /// energy error of the integrator
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().color_by)]
+    pub color_by: ColorBy,
+
+    /// how to estimate the moment of the flip inside the last step
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().flip_interp)]
+    pub flip_interp: FlipInterp,
+
+    /// stop the cells that have not the energy to flip before running them (drawn dark)
+    #[arg(long, action = ArgAction::Set, default_value_t = pendulum::Config::default().skip_never)]
+    pub skip_never: bool,
+
+    /// compute only one half of a picture that is its own mirror image, or check that it is
+    #[arg(long, value_enum, default_value_t = pendulum::Config::default().symmetry)]
+    pub symmetry: Symmetry,
+
+    /// mass of the first bob, unless it is on an axis. only the ratio m2 / m1 changes the motion
+    #[arg(long, default_value_t = pendulum::Config::default().m1)]
+    pub m1: f64,
+
+    /// mass of the second bob, unless it is on an axis
+    #[arg(long, default_value_t = pendulum::Config::default().m2)]
+    pub m2: f64,
+
+    /// gravity, unless it is on an axis
+    #[arg(long, allow_negative_numbers = true, default_value_t = pendulum::Config::default().g)]
+    pub g: f64,
+
+    /// worker threads for the physics, all cores by default
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// physics steps per frame in grid mode
+    #[arg(long, default_value_t = p2::Config::default().update_steps)]
+    pub update_steps: usize,
+
+    // ids of the options that were given on the command line
+    #[arg(skip)]
+    explicit: HashSet<String>,
+}
+
+// copy the fields given on the command line into the config
+macro_rules! override_fields {
+    ($args:expr, $config:expr, $($field:ident),*) => {
+        $(
+            if $args.explicit.contains(stringify!($field)) {
+                $config.$field = $args.$field;
+            }
+        )*
+    };
+}
+
+impl Args {
+    /// like `Args::parse`, but remembers which options were set explicitly
+    pub fn from_env() -> Self {
+        let matches = Args::command().get_matches();
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        args.explicit = matches
+            .ids()
+            .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.to_string())
+            .collect();
+        // the .json next to a field has its size, a bad file is reported by `config`
+        if let Some(Ok(Some((width, height)))) = args.config.as_deref().map(config::size) {
+            if !args.explicit.contains("width") {
+                args.width = width;
+            }
+            if !args.explicit.contains("height") {
+                args.height = height;
+            }
+        }
+        args
+    }
+
+    pub fn config(&self) -> Result<pendulum::Config, ConfigError> {
+        let mut config = match (&self.config, &self.preset) {
+            (Some(path), _) => config::load(path)?,
+            (None, Some(name)) => config::preset(name)?,
+            (None, None) => pendulum::Config::default(),
+        };
+        override_fields!(self, config.region, xmin, xmax, ymin, ymax);
+        config::check_size(self.width, self.height)?;
+        override_fields!(
+            self, config, x_axis, y_axis, color_step, color_mod, dive_diff, max_step, min_pixel, speed_a, speed_b,
+            integrator, equations, atol, rtol, policy, flip_count, color_by, lyap_time, lyap_scale,
+            flip_interp, skip_never, symmetry, theta1, theta2, dt1, dt2, l1, l2, m1, m2, g
+        );
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// size of the window in pixels
+    pub fn dims(&self) -> DVec2 {
+        dvec2(self.width, self.height)
+    }
+
+    /// how often to save the render, None for only when asked to
+    pub fn checkpoint_every(&self) -> Option<Duration> {
+        (self.checkpoint_minutes > 0.0).then(|| Duration::from_secs_f64(self.checkpoint_minutes * 60.0))
+    }
+
+    pub fn grid_config(&self) -> Result<p2::Config, ConfigError> {
+        if let Some(name) = &self.preset {
+            return Err(ConfigError::Invalid(format!("--preset {} only works with --mode fractal", name)));
+        }
+        // the grid always spans the lengths 10 .. 20 of both arms
+        if let Some(opt) = ["xmin", "xmax", "ymin", "ymax"].into_iter().find(|opt| self.explicit.contains(*opt)) {
+            return Err(ConfigError::Invalid(format!("--{} only works with --mode fractal", opt)));
+        }
+        let mut config = match &self.config {
+            Some(path) => config::load(path)?,
+            None => p2::Config::default(),
+        };
+        override_fields!(self, config, min_pixel, update_steps, integrator, atol, rtol);
+        config::check_size(self.width, self.height)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
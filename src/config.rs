@@ -0,0 +1,415 @@
+use std::f64::consts::{PI, TAU};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::pendulum::{FlipInterp, Symmetry};
+use crate::physics::{Equations, IntegratorKind, Quantity};
+use crate::policy::PolicyKind;
+use crate::region::Region;
+use crate::{field, p2, pendulum};
+
+/// bump this when the layout of a saved config changes, and teach `migrate` the old one
+pub const SCHEMA_VERSION: u64 = 2;
+
+pub const PRESETS: [&str; 6] = ["full", "baseline", "eye", "eye-context", "zoom-5.09-1.6", "lengths"];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    Write(PathBuf, String),
+    Format(PathBuf),
+    Version(u64),
+    UnknownPreset(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Write(path, e) => write!(f, "{}: can not write the config: {}", path.display(), e),
+            ConfigError::Format(path) => write!(f, "{}: expected a .toml or .json file", path.display()),
+            ConfigError::Version(v) => {
+                write!(f, "config schema version {} is newer than supported ({})", v, SCHEMA_VERSION)
+            }
+            ConfigError::UnknownPreset(name) => {
+                write!(f, "unknown preset '{}', expected one of: {}", name, PRESETS.join(", "))
+            }
+            ConfigError::Invalid(e) => write!(f, "invalid config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), ConfigError>;
+}
+
+fn check_region(r: &Region) -> Result<(), ConfigError> {
+    if ![r.xmin, r.xmax, r.ymin, r.ymax].iter().all(|v| v.is_finite()) {
+        return Err(ConfigError::Invalid(format!(
+            "region must be finite, got x: {} .. {}, y: {} .. {}",
+            r.xmin, r.xmax, r.ymin, r.ymax
+        )));
+    }
+    if r.width() <= 0.0 || r.height() <= 0.0 {
+        return Err(ConfigError::Invalid(format!(
+            "region is empty: x: {} .. {}, y: {} .. {}, min must be less than max",
+            r.xmin, r.xmax, r.ymin, r.ymax
+        )));
+    }
+    Ok(())
+}
+
+fn check_tolerance(atol: f64, rtol: f64) -> Result<(), ConfigError> {
+    // both zero would make rk45 shrink the step forever
+    if !(atol >= 0.0 && rtol >= 0.0 && atol + rtol > 0.0) {
+        return Err(ConfigError::Invalid(format!(
+            "tolerances must be >= 0 and not both 0, got atol = {}, rtol = {}",
+            atol, rtol
+        )));
+    }
+    Ok(())
+}
+
+fn check_axes(c: &pendulum::Config) -> Result<(), ConfigError> {
+    if c.x_axis == c.y_axis {
+        return Err(ConfigError::Invalid(format!("x_axis and y_axis are both {:?}", c.x_axis)));
+    }
+    let fixed = [
+        (Quantity::Theta1, c.theta1),
+        (Quantity::Theta2, c.theta2),
+        (Quantity::Dt1, c.dt1),
+        (Quantity::Dt2, c.dt2),
+        // unset arms follow the width, they are always fine
+        (Quantity::L1, c.l1.unwrap_or(1.0)),
+        (Quantity::L2, c.l2.unwrap_or(1.0)),
+        (Quantity::M1, c.m1),
+        (Quantity::M2, c.m2),
+        (Quantity::G, c.g),
+    ];
+    for (q, v) in fixed {
+        if !v.is_finite() || (q.positive() && v <= 0.0) {
+            return Err(ConfigError::Invalid(format!("bad value of {:?}: {}", q, v)));
+        }
+    }
+    // min < max is already checked
+    for (q, min) in [(c.x_axis, c.region.xmin), (c.y_axis, c.region.ymin)] {
+        if q.positive() && min <= 0.0 {
+            return Err(ConfigError::Invalid(format!("{:?} on an axis must start above 0, got {}", q, min)));
+        }
+    }
+    Ok(())
+}
+
+/// the baseline equations have no hamiltonian for the midpoint rule to solve
+fn check_equations(integrator: IntegratorKind, equations: Equations) -> Result<(), ConfigError> {
+    if integrator == IntegratorKind::Midpoint && equations != Equations::Lagrange {
+        return Err(ConfigError::Invalid("the midpoint integrator needs the lagrange equations".into()));
+    }
+    Ok(())
+}
+
+fn check_min_pixel(min_pixel: f64) -> Result<(), ConfigError> {
+    if min_pixel.is_nan() || min_pixel <= 0.0 {
+        return Err(ConfigError::Invalid(format!("min_pixel must be > 0, got {}", min_pixel)));
+    }
+    Ok(())
+}
+
+/// the size of the window or the image, in pixels
+pub fn check_size(width: f64, height: f64) -> Result<(), ConfigError> {
+    if !(width.is_finite() && height.is_finite() && width >= 1.0 && height >= 1.0) {
+        return Err(ConfigError::Invalid(format!("the size must be at least 1x1 pixels, got {}x{}", width, height)));
+    }
+    Ok(())
+}
+
+impl Validate for pendulum::Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        check_region(&self.region)?;
+        check_min_pixel(self.min_pixel)?;
+        check_tolerance(self.atol, self.rtol)?;
+        check_equations(self.integrator, self.equations)?;
+        check_axes(self)?;
+        if !(0.0..=1.0).contains(&self.dive_diff) {
+            return Err(ConfigError::Invalid(format!("dive_diff must be in 0..1, got {}", self.dive_diff)));
+        }
+        if self.max_step == 0 {
+            return Err(ConfigError::Invalid("max_step must be > 0".into()));
+        }
+        if self.color_mod == 0 {
+            return Err(ConfigError::Invalid("color_mod must be > 0".into()));
+        }
+        if self.flip_count == 0 {
+            return Err(ConfigError::Invalid("flip_count must be > 0".into()));
+        }
+        if !(self.lyap_time > 0.0 && self.lyap_time.is_finite() && self.lyap_scale > 0.0) {
+            return Err(ConfigError::Invalid(format!(
+                "lyap_time and lyap_scale must be > 0, got {} and {}",
+                self.lyap_time, self.lyap_scale
+            )));
+        }
+        // the cells would expire before the end
+        if self.policy == PolicyKind::Lyapunov && self.lyap_time >= self.max_step as f64 * pendulum::STEP_DELTA {
+            return Err(ConfigError::Invalid(format!(
+                "max_step must be beyond lyap_time, {} s is {} steps",
+                self.lyap_time,
+                (self.lyap_time / pendulum::STEP_DELTA).ceil()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for p2::Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        check_min_pixel(self.min_pixel)?;
+        check_equations(self.integrator, Equations::Baseline)?;
+        check_tolerance(self.atol, self.rtol)
+    }
+}
+
+pub fn preset(name: &str) -> Result<pendulum::Config, ConfigError> {
+    let config = match name {
+        "full" => pendulum::Config::default(),
+        // the picture of the first version, whatever the defaults become: its equations, whole steps, arms a
+        // quarter of the width and every cell run
+        "baseline" => pendulum::Config {
+            equations: Equations::Baseline,
+            flip_interp: FlipInterp::None,
+            l1: None,
+            l2: None,
+            skip_never: false,
+            symmetry: Symmetry::Off,
+            ..Default::default()
+        },
+        "eye" => pendulum::Config {
+            region: Region::new(1.24, 1.645, 1.45, 1.835),
+            color_step: 100.0,
+            dive_diff: 0.97,
+            max_step: 1_000_000,
+            min_pixel: 8.0,
+            color_mod: 6_000,
+            speed_a: 550.0,
+            speed_b: 20.0,
+            ..Default::default()
+        },
+        "eye-context" => pendulum::Config {
+            region: Region::new(0.56, 1.66, 1.01, 1.81),
+            color_step: 250.0,
+            dive_diff: 0.999,
+            max_step: 3_000_000,
+            min_pixel: 4.0,
+            // color_mod: 30700,
+            color_mod: 1200700,
+            speed_a: 620.0,
+            speed_b: 20.0,
+            ..Default::default()
+        },
+        "zoom-5.09-1.6" => pendulum::Config {
+            region: Region::new(5.09, 5.34, 1.6, 1.85),
+            color_step: 2000.0,
+            dive_diff: 0.994,
+            max_step: 10_000,
+            min_pixel: 4.0,
+            color_mod: 100_000,
+            ..Default::default()
+        },
+        // the slice of the experimental grid mode: lengths of the arms, first arm up
+        "lengths" => pendulum::Config {
+            x_axis: Quantity::L1,
+            y_axis: Quantity::L2,
+            region: Region::new(10.0, 20.0, 10.0, 20.0),
+            theta1: PI,
+            theta2: PI / 2.0,
+            max_step: 10_000,
+            ..Default::default()
+        },
+        _ => return Err(ConfigError::UnknownPreset(name.into())),
+    };
+    Ok(config)
+}
+
+#[derive(Serialize)]
+struct ConfigFile<T> {
+    version: u64,
+    #[serde(flatten)]
+    config:  T,
+}
+
+/// upgrade a config written by an older version to the current layout
+fn migrate(version: u64, mut value: Value) -> Value {
+    if version < 2 {
+        // 1 had flat xmin, ymin and the width and the height in xmax, ymax
+        if let Some(obj) = value.as_object_mut() {
+            let keys = ["xmin", "xmax", "ymin", "ymax"];
+            if keys.iter().any(|k| obj.contains_key(*k)) {
+                let mut get = |k: &str, default: f64| obj.remove(k).and_then(|v| v.as_f64()).unwrap_or(default);
+                let (xmin, width, ymin, height) = (get("xmin", 0.0), get("xmax", TAU), get("ymin", 0.0), get("ymax", PI));
+                let region = Region::new(xmin, xmin + width, ymin, ymin + height);
+                obj.insert("region".into(), serde_json::to_value(region).unwrap());
+            }
+        }
+    }
+    value
+}
+
+fn read_value(path: &Path) -> Result<Value, ConfigError> {
+    let ext = path.extension().and_then(|e| e.to_str());
+    if !matches!(ext, Some("toml") | Some("json")) {
+        return Err(ConfigError::Format(path.into()));
+    }
+    let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.into(), e))?;
+    let parse_err = |e: &dyn fmt::Display| ConfigError::Parse(path.into(), e.to_string());
+    if ext == Some("toml") {
+        let value: toml::Value = toml::from_str(&text).map_err(|e| parse_err(&e))?;
+        serde_json::to_value(value).map_err(|e| parse_err(&e))
+    }
+    else {
+        serde_json::from_str(&text).map_err(|e| parse_err(&e))
+    }
+}
+
+/// load a config from .toml or .json, upgrade it to the current schema and check the values
+pub fn load<T: DeserializeOwned + Validate>(path: &Path) -> Result<T, ConfigError> {
+    let mut value = read_value(path)?;
+    let version = match value.get("version") {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| ConfigError::Parse(path.into(), format!("bad schema version {}", v)))?,
+        None => return Err(ConfigError::Parse(path.into(), "missing schema version".into())),
+    };
+    if version > SCHEMA_VERSION {
+        return Err(ConfigError::Version(version));
+    }
+    if version < SCHEMA_VERSION {
+        value = migrate(version, value);
+        value["version"] = SCHEMA_VERSION.into();
+    }
+    // the configs reject unknown keys, which serde does not do through the flatten of ConfigFile, so
+    // they are read without it
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("version");
+        for k in field::SIDECAR_KEYS {
+            obj.remove(k);
+        }
+    }
+    let config: T = serde_json::from_value(value).map_err(|e| ConfigError::Parse(path.into(), e.to_string()))?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// the width and the height of the picture in the .json next to a field (`--npy`), None for a plain config
+pub fn size(path: &Path) -> Result<Option<(f64, f64)>, ConfigError> {
+    let value = read_value(path)?;
+    let get = |k: &str| value.get(k).and_then(Value::as_f64);
+    Ok(get("width").zip(get("height")))
+}
+
+pub fn save<T: Serialize>(path: &Path, config: &T) -> Result<(), ConfigError> {
+    let file = ConfigFile { version: SCHEMA_VERSION, config };
+    let text = match path.extension().and_then(|e| e.to_str()) {
+        // through Value, so the nested tables (region) go after the plain values
+        Some("toml") => toml::Value::try_from(&file)
+            .and_then(|v| toml::to_string_pretty(&v))
+            .map_err(|e| ConfigError::Write(path.into(), e.to_string()))?,
+        Some("json") => {
+            serde_json::to_string_pretty(&file).map_err(|e| ConfigError::Write(path.into(), e.to_string()))?
+        }
+        _ => return Err(ConfigError::Format(path.into())),
+    };
+    fs::write(path, text).map_err(|e| ConfigError::Io(path.into(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+
+    use super::{check_size, load, preset, save, size, ConfigError, Validate, PRESETS};
+    use crate::pendulum::Config;
+    use crate::physics::{Equations, IntegratorKind, Quantity};
+    use crate::policy::PolicyKind;
+    use crate::region::Region;
+
+    #[test]
+    fn test_presets_are_valid() {
+        for name in PRESETS {
+            preset(name).unwrap().validate().unwrap();
+        }
+        assert!(matches!(preset("nope"), Err(ConfigError::UnknownPreset(_))));
+    }
+
+    #[test]
+    fn test_save_load() {
+        for ext in ["toml", "json"] {
+            let path = temp_dir().join(format!("dpfrac-test-{}.{}", std::process::id(), ext));
+            let config = preset("eye").unwrap();
+            save(&path, &config).unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            assert!(text.contains("version"), "{}", text);
+            let loaded: Config = load(&path).unwrap();
+            assert_eq!(size(&path).unwrap(), None);
+            assert_eq!(loaded.region, config.region);
+            assert_eq!(loaded.max_step, config.max_step);
+
+            std::fs::write(&path, text.replace("0.97", "1.5")).unwrap();
+            let err = load::<Config>(&path).unwrap_err();
+            assert!(err.to_string().contains("dive_diff"), "{}", err);
+
+            // a mistyped key is an error, not a default
+            std::fs::write(&path, text.replace("dive_diff", "dive_dif")).unwrap();
+            let err = load::<Config>(&path).unwrap_err();
+            assert!(matches!(err, ConfigError::Parse(..)) && err.to_string().contains("dive_dif"), "{}", err);
+            std::fs::write(&path, text.replace("xmin", "x_min")).unwrap();
+            assert!(load::<Config>(&path).is_err());
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let ok = Config::default();
+        assert!(Config { min_pixel: 0.0, ..ok.clone() }.validate().is_err());
+        assert!(Config { max_step: 0, ..ok.clone() }.validate().is_err());
+        assert!(Config { flip_count: 0, ..ok.clone() }.validate().is_err());
+        assert!(Config { policy: PolicyKind::Lyapunov, max_step: 1000, ..ok.clone() }.validate().is_err());
+        assert!(Config { dive_diff: -0.1, ..ok.clone() }.validate().is_err());
+        assert!(Config { region: Region::new(1.0, 1.0, 0.0, 1.0), ..ok.clone() }.validate().is_err());
+        assert!(Config { region: Region::new(0.0, 1.0, 0.0, f64::INFINITY), ..ok.clone() }.validate().is_err());
+        assert!(Config { atol: 0.0, rtol: 0.0, ..ok.clone() }.validate().is_err());
+        assert!(Config { integrator: IntegratorKind::Midpoint, ..ok.clone() }.validate().is_err());
+        let lagrange = Config { equations: Equations::Lagrange, ..ok.clone() };
+        assert!(Config { integrator: IntegratorKind::Midpoint, ..lagrange }.validate().is_ok());
+        assert!(Config { rtol: f64::NAN, ..ok.clone() }.validate().is_err());
+        assert!(Config { m2: 0.0, ..ok.clone() }.validate().is_err());
+        assert!(Config { y_axis: Quantity::Theta1, ..ok.clone() }.validate().is_err());
+        assert!(Config { x_axis: Quantity::L1, ..ok.clone() }.validate().is_err());
+        assert!(Config { x_axis: Quantity::L1, region: Region::new(0.5, 1.0, 0.0, 1.0), ..ok.clone() }
+            .validate()
+            .is_ok());
+        assert!(ok.validate().is_ok());
+
+        assert!(check_size(0.0, 64.0).is_err() && check_size(64.0, -8.0).is_err());
+        assert!(check_size(f64::NAN, 64.0).is_err() && check_size(64.0, f64::INFINITY).is_err());
+        assert!(check_size(1.0, 64.0).is_ok());
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let path = temp_dir().join(format!("dpfrac-test-v1-{}.toml", std::process::id()));
+        std::fs::write(&path, "version = 1\nxmin = 1.24\nxmax = 0.5\nymax = 0.25\ndive_diff = 0.97\n").unwrap();
+        let loaded: Config = load(&path).unwrap();
+        assert_eq!(loaded.region, Region::new(1.24, 1.74, 0.0, 0.25));
+        assert_eq!(loaded.dive_diff, 0.97);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use ggez::graphics::Rect;
+use serde::Serialize;
+
+use crate::config::SCHEMA_VERSION;
+use crate::pendulum::{Config, STEP_DELTA};
+use crate::policy::PolicyKind;
+use crate::raster::pixel_span;
+
+/// value of the pixels of the cells that can never flip
+pub const NEVER: f32 = -1.0;
+
+/// the keys of the .json next to the field that are not part of the config, `config::load` skips them
+pub const SIDECAR_KEYS: [&str; 6] = ["width", "height", "dt", "units", "expired", "never"];
+
+/// flip time of every pixel in seconds (the lyapunov exponent in 1/s with that policy), row by row. NaN
+/// where the cell expired, NEVER where it has not the energy to flip
+pub struct FlipField {
+    pub width:  u32,
+    pub height: u32,
+    data:       Vec<f32>,
+}
+
+impl FlipField {
+    pub fn new(width: u32, height: u32) -> Self {
+        FlipField { width, height, data: vec![f32::NAN; (width * height) as usize] }
+    }
+
+    /// set every pixel whose center lies inside `rect`, the same pixels as `Raster::fill_rect`
+    pub fn fill_rect(&mut self, rect: Rect, v: f32) {
+        let (x0, y0, x1, y1) = pixel_span(rect, self.width, self.height);
+        for y in y0..y1 {
+            let row = (y * self.width) as usize;
+            self.data[row + x0 as usize..row + x1 as usize].fill(v);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// write as a NumPy .npy file of float32, shape (height, width)
+    pub fn save_npy(&self, path: &Path) -> io::Result<()> {
+        let mut header = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+            self.height, self.width
+        );
+        // magic, version and length take 10 bytes, the data starts aligned to 64
+        let len = (10 + header.len() + 1).div_ceil(64) * 64 - 10;
+        header = format!("{:<1$}\n", header, len - 1);
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(b"\x93NUMPY\x01\x00")?;
+        w.write_all(&(len as u16).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        for v in &self.data {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        w.flush()
+    }
+}
+
+/// what is needed to read the field and to render it again. the same layout as a saved config, so
+/// `--config` takes it
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    version: u64,
+    #[serde(flatten)]
+    config:  &'a Config,
+    width:   u32,
+    height:  u32,
+    // seconds per physics step, flip times divided by it are the step counts of the euler integrators
+    dt:      f64,
+    units:   &'static str,
+    expired: &'static str,
+    never:   f32,
+}
+
+/// save `field` to `path` (.npy) and its config to the same path with .json
+pub fn save(path: &Path, field: &FlipField, config: &Config) -> io::Result<()> {
+    field.save_npy(path)?;
+    let sidecar = Sidecar {
+        version: SCHEMA_VERSION,
+        config,
+        width: field.width,
+        height: field.height,
+        dt: STEP_DELTA,
+        units: if config.policy == PolicyKind::Lyapunov { "1/s" } else { "s" },
+        expired: "NaN",
+        never: NEVER,
+    };
+    let w = BufWriter::new(File::create(path.with_extension("json"))?);
+    serde_json::to_writer_pretty(w, &sidecar)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+
+    use ggez::graphics::Rect;
+
+    use super::{save, FlipField, NEVER};
+    use crate::config;
+    use crate::pendulum::Config;
+    use crate::region::Region;
+
+    #[test]
+    fn test_save() {
+        let mut field = FlipField::new(5, 3);
+        field.fill_rect(Rect::new(0.0, 0.0, 2.0, 3.0), 1.5);
+        field.fill_rect(Rect::new(4.0, 2.0, 1.0, 1.0), NEVER);
+        assert_eq!((field.get(1, 2), field.get(4, 2)), (1.5, NEVER));
+        assert!(field.get(2, 0).is_nan());
+
+        let config = Config { region: Region::new(1.0, 2.0, 3.0, 4.0), max_step: 1234, ..Default::default() };
+        let path = temp_dir().join(format!("dpfrac-test-{}.npy", std::process::id()));
+        save(&path, &field, &config).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let loaded: Config = config::load(&path.with_extension("json")).unwrap();
+        assert_eq!(config::size(&path.with_extension("json")).unwrap(), Some((5.0, 3.0)));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("json")).unwrap();
+
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + len]).unwrap();
+        assert!(header.contains("'shape': (3, 5)") && header.ends_with('\n'), "{}", header);
+        let data = bytes[10 + len..].chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(data.len(), 15);
+        assert_eq!((data[0], data[5 + 1], data[14]), (1.5, 1.5, NEVER));
+        assert!(data[2].is_nan());
+
+        assert_eq!((loaded.region, loaded.max_step), (config.region, config.max_step));
+    }
+}
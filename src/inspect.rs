@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+
+use ggez::graphics::{self, Color, DrawMode, Rect, Text};
+use ggez::*;
+use glam::*;
+
+use crate::pendulum::{Config, DoublePendulum, STEP_DELTA};
+use crate::physics::{Integrator, Params, State};
+use crate::policy::{PendulumPolicy, PolicyKind};
+
+// the replay reaches the flip in about this many frames
+const REPLAY_FRAMES: f64 = 600.0;
+const TRAIL: usize = 400;
+
+/// replays the initial condition of one cell as a big animated pendulum
+pub struct Inspector {
+    // the clicked cell, as it ended up
+    cell:        DoublePendulum,
+    // fresh copy of the cell that is being replayed
+    p:           DoublePendulum,
+    start:       State,
+    integrator:  Box<dyn Integrator + Send + Sync>,
+    max_step:    usize,
+    policy:      Box<dyn PendulumPolicy>,
+    // no flip, the cell has an exponent
+    lyapunov:    bool,
+    steps_frame: usize,
+    trail:       VecDeque<DVec2>,
+}
+
+impl Inspector {
+    pub fn new(cell: DoublePendulum, p: DoublePendulum, config: &Config) -> Self {
+        // expired and running cells have no flip time yet, use what they ran so far
+        let end = if cell.motion.stopped { cell.motion.flip_t } else { cell.motion.t };
+        let steps_frame = ((end / STEP_DELTA / REPLAY_FRAMES).ceil() as usize).max(1);
+        Inspector {
+            cell,
+            start: p.state(),
+            p,
+            integrator: config.integrator.build(config.atol, config.rtol),
+            max_step: config.max_step,
+            policy: config.policy(),
+            lyapunov: config.policy == PolicyKind::Lyapunov,
+            steps_frame,
+            trail: VecDeque::new(),
+        }
+    }
+
+    /// physics and start of the replayed cell
+    pub fn start(&self) -> (Params, State) {
+        (self.cell.params(), self.start)
+    }
+
+    pub fn update(&mut self) -> GameResult<()> {
+        if self.p.motion.stopped {
+            return Ok(());
+        }
+        self.p.update(self.steps_frame, self.max_step, &*self.integrator, &*self.policy)?;
+        self.trail.push_back(self.bobs(1.0).1);
+        if self.trail.len() > TRAIL {
+            self.trail.pop_front();
+        }
+        Ok(())
+    }
+
+    /// positions of the bobs relative to the pivot, the arms together are `r` long
+    fn bobs(&self, r: f64) -> (DVec2, DVec2) {
+        let (m, l1, l2) = (&self.p.motion, self.p.motion.params.l1, self.p.motion.params.l2);
+        let (a1, a2) = (r * l1 / (l1 + l2), r * l2 / (l1 + l2));
+        let (ts1, tc1) = m.state.theta1.sin_cos();
+        let (ts2, tc2) = m.state.theta2.sin_cos();
+        let b1 = dvec2(ts1 * a1, tc1 * a1);
+        (b1, b1 + dvec2(ts2 * a2, tc2 * a2))
+    }
+
+    fn text(&self) -> String {
+        let (c, p) = (&self.cell.motion, &self.cell.params());
+        let mut lines = vec![
+            format!("theta1 = {:.5}  theta2 = {:.5}", self.start.theta1, self.start.theta2),
+            format!("dt1 = {:.5}  dt2 = {:.5}", self.start.dt1, self.start.dt2),
+            format!("l1 = {}  l2 = {}  m1 = {}  m2 = {}  g = {}", p.l1, p.l2, p.m1, p.m2, p.g),
+            format!("t = {:.2} s  step {}", self.p.motion.t, self.p.motion.steps),
+        ];
+        lines.push(match (c.stopped, c.expired) {
+            _ if c.never => "never flips: the energy is below the one of the arm upright".into(),
+            (true, false) if self.lyapunov => {
+                format!("lyapunov exponent {:.4} /s over {:.0} s", self.cell.lyapunov(), c.t)
+            }
+            (true, false) => match c.flip {
+                Some(flip) if c.flips > 1 => {
+                    format!("flip {} at {:.4} s, step {}, {}", c.flips, c.flip_t, c.steps, flip)
+                }
+                Some(flip) => format!("flip at {:.4} s, step {}, {}", c.flip_t, c.steps, flip),
+                None => format!("flip at {:.4} s, step {}", c.flip_t, c.steps),
+            },
+            (true, true) => format!("no flip in {:.2} s ({} steps), expired", c.t, c.steps),
+            (false, _) => format!("still running after {:.2} s", c.t),
+        });
+        if self.p.motion.stopped && !self.p.expired() && !self.lyapunov {
+            lines.push("FLIP".into());
+        }
+        lines.join("\n")
+    }
+
+    /// draw the panel in the top right corner of a window of `dims` pixels
+    pub fn draw(&self, ctx: &mut Context, dims: DVec2) -> GameResult<()> {
+        let side = dims.min_element() / 2.0;
+        let panel = Rect::new((dims.x - side) as f32, 0.0, side as f32, side as f32);
+        let center = dvec2(dims.x - side / 2.0, side / 2.0);
+        let r = side * 0.45;
+
+        let mb = &mut graphics::MeshBuilder::new();
+        mb.rectangle(DrawMode::fill(), panel, Color::new(0.0, 0.0, 0.0, 0.85))?;
+        mb.rectangle(DrawMode::stroke(1.0), panel, Color::WHITE)?;
+        let trail = self.trail.iter().map(|b| (center + *b * r).as_vec2()).collect::<Vec<_>>();
+        if trail.len() >= 2 {
+            mb.line(&trail, 1.0, Color::new(0.4, 0.6, 1.0, 0.6))?;
+        }
+        let (b1, b2) = self.bobs(r);
+        let (p0, p1, p2) = (center.as_vec2(), (center + b1).as_vec2(), (center + b2).as_vec2());
+        mb.line(&[p0, p1, p2], 3.0, Color::WHITE)?;
+        let flipped = self.p.motion.stopped && !self.p.expired() && !self.lyapunov;
+        let bob2 = if flipped { Color::RED } else { Color::WHITE };
+        mb.circle(DrawMode::fill(), p1, 8.0, 0.5, Color::WHITE)?;
+        mb.circle(DrawMode::fill(), p2, if flipped { 14.0 } else { 8.0 }, 0.5, bob2)?;
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, (vec2(0.0, 0.0),))?;
+
+        let text = Text::new(self.text());
+        graphics::draw(ctx, &text, (vec2(panel.x + 8.0, panel.y + 8.0), Color::WHITE))?;
+        Ok(())
+    }
+}
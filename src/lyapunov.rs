@@ -0,0 +1,168 @@
+use angular_units::{Angle, Rad};
+use ggez::graphics::Color;
+
+use crate::pendulum::{Config, Motion};
+use crate::physics::{Integrator, State};
+use crate::policy::{arm_color, drift_color, drift_shade, gray, rgb, ColorBy, PendulumPolicy};
+
+/// distance of the shadow from the pendulum after a renormalization, radians and rad/s together
+const SEPARATION: f64 = 1e-8;
+
+/// steps between two renormalizations of the shadow, it must stay close enough to grow linearly
+const RENORM_STEPS: usize = 10;
+
+fn distance(a: &State, b: &State) -> f64 {
+    let d = [a.theta1 - b.theta1, a.theta2 - b.theta2, a.dt1 - b.dt1, a.dt2 - b.dt2];
+    d.iter().map(|v| v * v).sum::<f64>().sqrt()
+}
+
+/// the start SEPARATION away from `s` in the direction of `toward`, along the diagonal if it is `s`
+fn near(s: &State, toward: &State) -> State {
+    let d = distance(s, toward);
+    let u = if d > 0.0 && d.is_finite() {
+        [toward.theta1 - s.theta1, toward.theta2 - s.theta2, toward.dt1 - s.dt1, toward.dt2 - s.dt2].map(|v| v / d)
+    }
+    else {
+        [0.5; 4]
+    };
+    State {
+        theta1: s.theta1 + SEPARATION * u[0],
+        theta2: s.theta2 + SEPARATION * u[1],
+        dt1:    s.dt1 + SEPARATION * u[2],
+        dt2:    s.dt2 + SEPARATION * u[3],
+    }
+}
+
+/// `s` moved by `to - from`: `near(s, shifted(s, from, to))` keeps the direction of the separation from `from` to `to`
+fn shifted(s: &State, from: &State, to: &State) -> State {
+    State {
+        theta1: s.theta1 + (to.theta1 - from.theta1),
+        theta2: s.theta2 + (to.theta2 - from.theta2),
+        dt1:    s.dt1 + (to.dt1 - from.dt1),
+        dt2:    s.dt2 + (to.dt2 - from.dt2),
+    }
+}
+
+/// finite-time largest lyapunov exponent of a stopped cell, 1/s
+pub fn exponent(m: &Motion) -> f64 {
+    m.lyap / m.t
+}
+
+/// runs every cell for the same time next to a shadow that starts SEPARATION away, and colors it by
+/// the mean rate at which the two drift apart: the finite-time largest lyapunov exponent. the shadow is
+/// moved back to SEPARATION every RENORM_STEPS steps
+pub struct LyapunovPolicy {
+    // the shadow takes the same steps as the pendulum
+    integrator: Box<dyn Integrator + Send + Sync>,
+    time:       f64,
+    // exponent of a full color scale, 1/s
+    scale:      f64,
+    color_by:   ColorBy,
+}
+
+impl LyapunovPolicy {
+    pub fn new(config: &Config) -> Self {
+        LyapunovPolicy {
+            integrator: config.integrator.build(config.atol, config.rtol),
+            time:       config.lyap_time,
+            scale:      config.lyap_scale,
+            color_by:   config.color_by,
+        }
+    }
+
+    /// position 0..1 of the exponent on the color scale
+    fn shade(&self, m: &Motion) -> f64 {
+        (exponent(m) / self.scale).clamp(0.0, 1.0)
+    }
+}
+
+impl PendulumPolicy for LyapunovPolicy {
+    fn stop(&self, m: &mut Motion, before: &State, dt: f64) -> bool {
+        if m.steps == 1 {
+            m.shadow = near(before, before);
+        }
+        let last = m.shadow;
+        let mut h = dt;
+        if self.integrator.step(&m.params, &mut m.shadow, &mut h) != dt {
+            // the adaptive integrator took another step for the shadow, start it again in the direction it had
+            m.lyap += (distance(before, &last) / SEPARATION).ln();
+            m.shadow = near(&m.state, &shifted(&m.state, before, &last));
+        }
+        let done = m.t >= self.time;
+        if m.steps.is_multiple_of(RENORM_STEPS) || done {
+            m.lyap += (distance(&m.state, &m.shadow) / SEPARATION).ln();
+            m.shadow = near(&m.state, &m.shadow);
+        }
+        if done {
+            m.flip_t = m.t;
+        }
+        done
+    }
+
+    fn never(&self, _m: &Motion) -> bool {
+        false
+    }
+
+    fn color(&self, m: &Motion) -> Color {
+        if self.color_by == ColorBy::Drift && !m.never {
+            return drift_color(m);
+        }
+        gray(m).unwrap_or_else(|| {
+            // dark blue for the regular cells to bright red for the chaotic ones
+            let x = self.shade(m);
+            rgb(prisma::Hsv::new(Rad::new(4.2 * (1.0 - x)), 1.0, (0.3 + 0.7 * x) as f32).into())
+        })
+    }
+
+    fn pcolor(&self, m: &Motion) -> Color {
+        arm_color(m)
+    }
+
+    fn differ(&self, a: &Motion, b: &Motion, dive_diff: f64) -> bool {
+        if self.color_by == ColorBy::Drift && !a.never && !b.never {
+            return (drift_shade(a) - drift_shade(b)).abs() > 1.0 - dive_diff;
+        }
+        (self.shade(a) - self.shade(b)).abs() > 1.0 - dive_diff
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::*;
+
+    use super::{distance, exponent, near, shifted, LyapunovPolicy, SEPARATION};
+    use crate::pendulum::{Config, DoublePendulum};
+    use crate::physics::{Equations, SemiImplicitEuler, State};
+    use crate::policy::{PendulumPolicy, PolicyKind};
+
+    #[test]
+    fn test_exponent() {
+        let config = Config { policy: PolicyKind::Lyapunov, equations: Equations::Lagrange, ..Default::default() };
+        let policy = LyapunovPolicy::new(&config);
+        let run = |x: f64, y: f64| {
+            let mut m = DoublePendulum::new2(dvec2(x, y), dvec2(100.0, 100.0), 1.0, &config).motion;
+            m.update(1_000_000, config.max_step, &SemiImplicitEuler, &policy);
+            assert!(m.stopped && !m.expired && m.flip_t >= config.lyap_time);
+            m
+        };
+        // small swings are regular, the first arm sideways and the second one high up is chaotic
+        let (regular, chaotic) = (run(5.0, 5.0), run(50.0, 75.0));
+        assert!(exponent(&regular).abs() < 0.01, "{}", exponent(&regular));
+        assert!(exponent(&chaotic) > 0.05, "{}", exponent(&chaotic));
+        assert!(policy.differ(&regular, &chaotic, config.dive_diff));
+        assert_ne!(policy.color(&regular), policy.color(&chaotic));
+    }
+
+    #[test]
+    fn test_restart() {
+        // a shadow started again after a step keeps the direction it had from the pendulum
+        let before = State { theta1: 1.0, theta2: 2.0, dt1: 0.5, dt2: -0.5 };
+        let last = State { theta1: 1.0 + 3e-9, theta2: 2.0 - 4e-9, ..before };
+        let now = State { theta1: 1.01, theta2: 1.99, dt1: 0.49, dt2: -0.48 };
+        let shadow = near(&now, &shifted(&now, &before, &last));
+        assert!((distance(&now, &shadow) - SEPARATION).abs() < 1e-15);
+        let d = [shadow.theta1 - now.theta1, shadow.theta2 - now.theta2, shadow.dt1 - now.dt1, shadow.dt2 - now.dt2];
+        let want = [0.6, -0.8, 0.0, 0.0].map(|v| v * SEPARATION);
+        assert!(d.iter().zip(want).all(|(d, w)| (d - w).abs() < 1e-15), "{:?}", d);
+    }
+}
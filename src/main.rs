@@ -5,31 +5,84 @@ extern crate prisma;
 extern crate tap;
 
 use std::env::current_dir;
-use std::f64::consts::{TAU, PI};
-use std::path::Path;
-use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use ggez::conf::{WindowMode, WindowSetup, ModuleConf};
 use ggez::event::{self, EventHandler, KeyCode, KeyMods};
-use ggez::graphics::{self, Canvas, Color, Font, Text, TextFragment, Rect};
-use ggez::{timer, Context, ContextBuilder, GameResult};
+use ggez::graphics::{self, Color, Font, Text, TextFragment, Rect};
+use ggez::{Context, ContextBuilder, GameResult};
 use glam::*;
+use checkpoint::Autosave;
+use cli::{Args, Mode};
+use inspect::Inspector;
+use config::Validate;
 use p2::{PendulumFamily2};
-use pendulum::{DoublePendulum, PendulumFamily, Config};
+use poincare::{PoincareView, Section};
+use pendulum::{PendulumFamily, Config};
+use policy::PolicyKind;
+use raster::Raster;
+use region::Region;
 use tap::Tap;
 
 mod pendulum;
+mod arena;
 mod avgspeed;
+mod batch;
+mod checkpoint;
+mod cli;
+mod config;
+mod field;
+mod inspect;
+mod lyapunov;
 mod p2;
-
-const WIDTH: f64 = 2048.0;
+mod physics;
+mod poincare;
+mod policy;
+mod quadtree;
+mod raster;
+mod region;
 
 fn main() {
+    let args = Args::from_env();
+    if let Some(n) = args.threads {
+        // the pool lives for the whole run, frames only hand work to it
+        rayon::ThreadPoolBuilder::new().num_threads(n).build_global().expect("could not start the worker threads");
+    }
+    if let Some(v) = &args.poincare {
+        if let Err(e) = poincare(&args, dvec2(v[0], v[1])) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+    let pendulums = family(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+
+    let exports = Exports { npy: args.npy.clone(), cells: args.cells.clone() };
+    if let Some(out) = &args.headless {
+        let pendulums = match pendulums {
+            Family::Fractal(f) => f,
+            Family::Grid(_) => {
+                eprintln!("--headless only supports --mode fractal");
+                std::process::exit(2);
+            }
+        };
+        let autosave = Autosave::new(args.checkpoint.clone(), args.checkpoint_every());
+        run_headless(*pendulums, autosave, out, &exports).expect("headless render failed");
+        return;
+    }
+
+    // a resumed render keeps its size
+    let dims = match &pendulums {
+        Family::Fractal(f) => f.dims(),
+        Family::Grid(_) => args.dims(),
+    };
     // Make a Context.
-    let mut window_mode = WindowMode::default();
-    window_mode.width = WIDTH as f32;
-    window_mode.height = WIDTH as f32;
-    
+    let window_mode = WindowMode::default().dimensions(dims.x as f32, dims.y as f32);
+
     let window_setup = WindowSetup::default().tap_mut(|x| x.title="Double pendulum fractal".into());
 
     let module_conf = ModuleConf::default().audio(false);
@@ -46,31 +99,135 @@ fn main() {
     // Create an instance of your event handler.
     // Usually, you should provide it with the Context object to
     // use when setting your game up.
-    let my_game = MyGame::new(&mut ctx);
+    let autosave = Autosave::new(args.checkpoint.clone(), args.checkpoint_every());
+    let section = (args.poincare_csv.clone(), args.poincare_time);
+    let my_game = MyGame::new(&mut ctx, pendulums, autosave, exports, section);
 
     // Run!
     event::run(ctx, event_loop, my_game);
 }
 
+/// build the pendulums from the command line, saving the config if asked to
+fn family(args: &Args) -> Result<Family, Box<dyn std::error::Error>> {
+    let family = match args.mode {
+        Mode::Fractal if args.resume => {
+            let f = checkpoint::load(&args.checkpoint)?;
+            println!("resumed {}: iter {}, done {}, active {}", args.checkpoint.display(), f.iter, f.done(), f.len());
+            Family::Fractal(Box::new(f))
+        }
+        Mode::Grid if args.resume => return Err("--resume only supports --mode fractal".into()),
+        Mode::Fractal => {
+            let config = args.config()?;
+            if let Some(path) = &args.save_config {
+                config::save(path, &config)?;
+            }
+            let mut f = PendulumFamily::new(config, args.dims());
+            f.init();
+            Family::Fractal(Box::new(f))
+        }
+        Mode::Grid => {
+            let config = args.grid_config()?;
+            if let Some(path) = &args.save_config {
+                config::save(path, &config)?;
+            }
+            let mut f = PendulumFamily2::new(config, args.dims());
+            f.init();
+            Family::Grid(f)
+        }
+    };
+    Ok(family)
+}
+
+/// the fractions of the width and the height of a window of `dims` pixels at the point `v`
+fn frac(v: Vec2, dims: DVec2) -> DVec2 {
+    dvec2(v.x as f64 / dims.x, v.y as f64 / dims.y)
+}
+
+/// write the poincare section of the pendulum at the axis values `v` and exit
+fn poincare(args: &Args, v: DVec2) -> Result<(), Box<dyn std::error::Error>> {
+    let config = args.config()?;
+    let (params, start) = config.start_at(v, args.dims().x);
+    let integrator = config.integrator.build(config.atol, config.rtol);
+    let mut section = Section::new(params, start, pendulum::STEP_DELTA);
+    while section.t < args.poincare_time {
+        section.run(&*integrator, 10_000);
+    }
+    section.save_csv(&args.poincare_csv)?;
+    println!("poincare: {} points in {:.0} s, saved {}", section.points.len(), section.t, args.poincare_csv.display());
+    Ok(())
+}
+
+/// files written when the render ends, besides the picture
+struct Exports {
+    // flip times as .npy, the config as .json next to it
+    npy:   Option<PathBuf>,
+    // the cells of the quadtree as .csv or .json
+    cells: Option<PathBuf>,
+}
+
+impl Exports {
+    fn save(&self, pendulums: &PendulumFamily) -> GameResult<()> {
+        if let Some(path) = &self.npy {
+            match pendulums.flip_field() {
+                Some(f) => {
+                    field::save(path, &f, pendulums.config())?;
+                    println!("saved {} and {}", path.display(), path.with_extension("json").display());
+                }
+                None => eprintln!("{}: not saved, {} cells are still running", path.display(), pendulums.len()),
+            }
+        }
+        if let Some(path) = &self.cells {
+            quadtree::save(path, pendulums)?;
+            println!("saved {} cells to {}", pendulums.cells.len(), path.display());
+        }
+        Ok(())
+    }
+}
+
+/// run the simulation to the end without a window and save the result as png
+fn run_headless(mut pendulums: PendulumFamily, mut autosave: Autosave, out: &Path, exports: &Exports) -> GameResult<()> {
+    let dims = pendulums.dims();
+    let mut img = Raster::new(dims.x as u32, dims.y as u32);
+    loop {
+        pendulums.update()?;
+        pendulums.rasterize(&mut img);
+        autosave.tick(&pendulums);
+        if pendulums.len() == 0 {
+            break;
+        }
+    }
+    println!("===END===");
+    img.save_png(out)?;
+    println!("saved {}", out.display());
+    exports.save(&pendulums)?;
+    Ok(())
+}
+
 #[derive(PartialEq)]
 enum GameState {
-    PAUSE,
-    RUN,
-    DONE,
+    Pause,
+    Run,
+    Done,
 }
 
 struct TextHint {
-    font: Font,
-    text: Option<String>,
-    pos:  Vec2,
+    font:  Font,
+    text:  Option<String>,
+    pos:   Vec2,
+    // the text is stale: the mouse moved or the picture changed
+    moved: bool,
+    // the cell under the mouse and its hover_key when the text was made
+    cell:  Option<(usize, (bool, usize))>,
 }
 
 impl TextHint {
     fn new(ctx: &mut Context) -> GameResult<Self> {
         Ok(TextHint {
-            font: Font::new(ctx, "/OpenSans-Regular.ttf")?,
-            text: None,
-            pos:  vec2(0.0, 0.0),
+            font:  Font::new(ctx, "/OpenSans-Regular.ttf")?,
+            text:  None,
+            pos:   vec2(0.0, 0.0),
+            moved: true,
+            cell:  None,
         })
     }
 
@@ -78,108 +235,96 @@ impl TextHint {
         if self.text.is_none() {
             return Ok(());
         }
-        let text = Text::new(self.text.clone().unwrap());
-        let mut pos = self.pos.clone();
+        let text = Text::new(TextFragment::new(self.text.clone().unwrap()).font(self.font).scale(18.0));
+        let (w, h) = (text.width(ctx), text.height(ctx));
+        let (sw, sh) = graphics::drawable_size(ctx);
+        let mut pos = self.pos;
         pos.y -= 20.0;
         pos.x += 10.0;
+        // keep it on the screen, flip to the other side of the cursor near the edges
+        if pos.x + w > sw {
+            pos.x = self.pos.x - 10.0 - w;
+        }
+        pos.y = pos.y.min(sh - h).max(0.0);
+        let bg = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            Rect::new(pos.x - 4.0, pos.y - 2.0, w + 8.0, h + 4.0),
+            Color::new(0.0, 0.0, 0.0, 0.7),
+        )?;
+        graphics::draw(ctx, &bg, (vec2(0.0, 0.0),))?;
         graphics::draw(ctx, &text, (pos, Color::WHITE))?;
         Ok(())
     }
 }
 
+enum Family {
+    Fractal(Box<PendulumFamily>),
+    Grid(PendulumFamily2),
+}
+
+impl Family {
+    fn update(&mut self) -> GameResult<()> {
+        match self {
+            Family::Fractal(f) => f.update(),
+            Family::Grid(f) => f.update(),
+        }
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        match self {
+            Family::Fractal(f) => f.draw(ctx),
+            Family::Grid(f) => f.draw(ctx),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Family::Fractal(f) => f.len(),
+            Family::Grid(f) => f.len(),
+        }
+    }
+}
+
 struct MyGame {
-    pendulums: PendulumFamily,
+    pendulums: Family,
     state:     GameState,
     hint:      TextHint,
+    // corners of the box being dragged, window pixels
+    drag:      Option<(Vec2, Vec2)>,
+    // regions shown before the current one
+    back:      Vec<Region>,
+    // replay of the clicked cell
+    inspector: Option<Inspector>,
+    // poincare section of the replayed cell, and where C saves it
+    poincare:  Option<PoincareView>,
+    section:   (PathBuf, f64),
+    autosave:  Autosave,
+    // what to save when the render ends
+    exports:   Exports,
 }
 
 impl MyGame {
-    pub fn new(ctx: &mut Context) -> MyGame {
-        // let config = Config {
-        //     xmin: 0.0,
-        //     xmax: TAU,
-        //     ymin: 0.0,
-        //     ymax: PI,
-        //     color_step: 100.0,
-        //     dive_diff: 0.990,
-        //     max_step: 50_000,
-        //     min_pixel: 4.0,
-        //     color_mod: 6000,
-        //     speed_a: 550.0,
-        //     speed_b: 20.0,
-        // };
-        let config = Config {
-            xmin: 0.0,
-            xmax: TAU,
-            ymin: 0.0,
-            ymax: PI,
-            color_step: 100.0,
-            dive_diff: 0.82,
-            max_step: 460_000,
-            min_pixel: 4.0,
-            color_mod: 6000,
-            speed_a: 550.0,
-            speed_b: 20.0,
-        };
-        // the eye
-        // let config = Config {
-        //     xmin: 1.24,
-        //     xmax: 0.405,
-        //     ymin: 1.45,
-        //     ymax: 0.385,
-        //     color_step: 100.0,
-        //     dive_diff: 0.97,
-        //     max_step: 1_000_000,
-        //     min_pixel: 8.0,
-        //     color_mod: 6_000,
-        //     speed_a: 550.0,
-        //     speed_b: 20.0,
-        // };
-        // eye + more context
-        // let config = Config {
-        //     xmin: 0.56,
-        //     xmax: 1.1,
-        //     ymin: 1.01,
-        //     ymax: 0.8,
-        //     color_step: 250.0,
-        //     dive_diff: 0.999,
-        //     max_step: 3_000_000,
-        //     min_pixel: 4.0,
-        //     // color_mod: 30700,
-        //     color_mod: 1200700,
-        //     speed_a: 620.0,
-        //     speed_b: 20.0,
-        // };
-        // let config = Config {
-        //     xmin: 5.09,
-        //     xmax: 0.25,
-        //     ymin: 1.6,
-        //     ymax: 0.25,
-        //     color_step: 2000.0,
-        //     dive_diff: 0.994,
-        //     max_step: 10_000,
-        //     min_pixel: 4.0,
-        //     color_mod: 100_000,
-        // };
-
-        // let config = p2::Config {
-        //     xmin: 0.0,
-        //     xmax: TAU,
-        //     ymin: 0.0,
-        //     ymax: PI,
-        //     min_pixel: 8.0,
-        //     update_steps: 10,
-        // };
-        
+    pub fn new(
+        ctx: &mut Context,
+        pendulums: Family,
+        autosave: Autosave,
+        exports: Exports,
+        section: (PathBuf, f64),
+    ) -> MyGame {
         let mut this = MyGame {
-            pendulums: PendulumFamily::new(ctx, config.clone(), WIDTH),
-            // pendulums: PendulumFamily2::new(ctx, config.clone(), WIDTH),
-            state:     GameState::PAUSE,
-            // state: GameState::RUN,
+            pendulums,
+            state:     GameState::Pause,
+            // state: GameState::Run,
             hint:      TextHint::new(ctx).unwrap(),
+            drag:      None,
+            back:      Vec::new(),
+            inspector: None,
+            poincare:  None,
+            section,
+            autosave,
+            exports,
         };
-        //this.pendulums.init();
-        this.pendulums.add(DoublePendulum::new2(dvec2(WIDTH / 2.0, WIDTH / 2.0), WIDTH, 1.0, &config));
         // this.pendulums.add(DoublePendulum::new2(vec2(768.0, 768.0), WIDTH, 0.25));
         // this.pendulums.add(DoublePendulum::new2(vec2(WIDTH / 4.0, WIDTH / 4.0 * 3.0), WIDTH, 0.5));
         // for _ in 1..2300 {
@@ -195,20 +340,123 @@ impl MyGame {
 }
 
 impl MyGame {
+    /// the quantities under the cursor and the cell there, if it is done
+    fn update_hint(&mut self) {
+        let f = match &self.pendulums {
+            Family::Fractal(f) => f,
+            Family::Grid(_) => return,
+        };
+        // only the cell under the mouse stopping or splitting changes the text until the mouse moves
+        if !self.hint.moved && self.hint.cell.is_none_or(|(id, key)| f.hover_key(id) == key) {
+            return;
+        }
+        self.hint.moved = false;
+        let (x, y) = (self.hint.pos.x as f64, self.hint.pos.y as f64);
+        let dims = f.dims();
+        if x < 0.0 || y < 0.0 || x >= dims.x || y >= dims.y {
+            (self.hint.text, self.hint.cell) = (None, None);
+            return;
+        }
+        let config = f.config();
+        let v = config.region.at(x / dims.x, y / dims.y);
+        let mut text = format!("{} = {:.6}\n{} = {:.6}", config.x_axis, v.x, config.y_axis, v.y);
+        let id = f.find(x, y);
+        self.hint.cell = id.map(|id| (id, f.hover_key(id)));
+        if let Some(id) = id {
+            let (p, r) = (&f.cells.motion[id], f.cells.rect(id));
+            if p.never {
+                text += &format!("\nnever flips: too little energy\ncell {}x{} px", r.w, r.h);
+            }
+            else if p.stopped {
+                let what = match p.flip {
+                    _ if p.expired => "expired".into(),
+                    _ if config.policy == PolicyKind::Lyapunov => format!("lyapunov {:.4} /s", lyapunov::exponent(p)),
+                    Some(flip) => format!("flip ({})", flip),
+                    None => "flip".into(),
+                };
+                text += &format!(
+                    "\n{}: {:.3} s, {} steps\ncell {}x{} px",
+                    what,
+                    p.flip_t,
+                    p.steps,
+                    r.w,
+                    r.h
+                );
+            }
+        }
+        self.hint.text = Some(text);
+    }
+
+    /// restart the fractal on `region`, returns false if the config does not accept it
+    fn show(&mut self, region: Region) -> bool {
+        let f = match &mut self.pendulums {
+            Family::Fractal(f) => f,
+            Family::Grid(_) => return false,
+        };
+        let config = Config { region, ..f.config().clone() };
+        if let Err(e) = config.validate() {
+            println!("{}", e);
+            return false;
+        }
+        println!(
+            "region: --xmin {} --xmax {} --ymin {} --ymax {}",
+            region.xmin, region.xmax, region.ymin, region.ymax
+        );
+        let mut next = PendulumFamily::new(config, f.dims());
+        next.init();
+        **f = next;
+        self.hint.moved = true;
+        true
+    }
+
+    /// open the replay of the cell under (x, y)
+    fn inspect(&mut self, x: f64, y: f64) {
+        if let Family::Fractal(f) = &self.pendulums {
+            if let Some(cell) = f.find_all(x, y) {
+                let p = f.replay(&cell);
+                self.inspector = Some(Inspector::new(cell, p, f.config()));
+                self.poincare = None;
+            }
+        }
+    }
+
+    /// remember the current region and show another one
+    fn zoom(&mut self, region: Region) {
+        if let Family::Fractal(f) = &self.pendulums {
+            let current = f.config().region;
+            if self.show(region) {
+                self.back.push(current);
+                self.inspector = None;
+                self.poincare = None;
+            }
+        }
+    }
 }
 
 impl EventHandler for MyGame {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        // self.update_hint();
-        if self.state == GameState::PAUSE {
+        self.update_hint();
+        if let Some(inspector) = &mut self.inspector {
+            inspector.update()?;
+        }
+        if let Some(poincare) = &mut self.poincare {
+            poincare.update();
+        }
+        if self.state == GameState::Pause {
             return Ok(());
         }
-        self.pendulums.update(ctx)?;
+        self.pendulums.update()?;
+        if let Family::Fractal(f) = &self.pendulums {
+            self.autosave.tick(f);
+        }
         // let img = graphics::screenshot(ctx).unwrap();
         // img.encode(ctx, graphics::ImageFormat::Png, format!("/{:06}.png", self.pendulums.iter)).unwrap();
-        if self.pendulums.len() == 0 && self.state == GameState::RUN {
+        if self.pendulums.len() == 0 && self.state == GameState::Run {
             println!("===END===");
-            self.state = GameState::PAUSE;
+            self.state = GameState::Pause;
+            if let Family::Fractal(f) = &self.pendulums {
+                self.exports.save(f)?;
+            }
         }
         Ok(())
     }
@@ -216,7 +464,24 @@ impl EventHandler for MyGame {
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         let t = Instant::now();
         self.pendulums.draw(ctx)?;
-        // self.hint.draw(ctx)?;
+        if let (Some((start, end)), Family::Fractal(f)) = (self.drag, &self.pendulums) {
+            // the box that will be shown, with the aspect of the window
+            let dims = f.dims();
+            let (a, b) = region::fit(frac(start, dims), frac(end, dims));
+            let (p, size) = (a * dims, (b - a) * dims);
+            let rect = Rect::new(p.x as f32, p.y as f32, size.x as f32, size.y as f32);
+            if rect.w > 0.0 && rect.h > 0.0 {
+                let mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), rect, Color::WHITE)?;
+                graphics::draw(ctx, &mesh, (vec2(0.0, 0.0),))?;
+            }
+        }
+        if let (Some(inspector), Family::Fractal(f)) = (&self.inspector, &self.pendulums) {
+            inspector.draw(ctx, f.dims())?;
+        }
+        if let (Some(poincare), Family::Fractal(f)) = (&self.poincare, &self.pendulums) {
+            poincare.draw(ctx, f.dims())?;
+        }
+        self.hint.draw(ctx)?;
         let res = graphics::present(ctx);
         // println!("draw: {:?}", t.elapsed());
         res
@@ -228,16 +493,58 @@ impl EventHandler for MyGame {
                 event::quit(ctx);
             }
             KeyCode::Equals => {
-                self.pendulums.update_steps *= 2;
+                if let Family::Fractal(f) = &mut self.pendulums {
+                    f.update_steps *= 2;
+                }
+            }
+            KeyCode::Minus => {
+                if let Family::Fractal(f) = &self.pendulums {
+                    let region = f.config().region.scaled(2.0);
+                    self.zoom(region);
+                }
+            }
+            KeyCode::Back => {
+                if let Some(region) = self.back.pop() {
+                    self.show(region);
+                    self.inspector = None;
+                    self.poincare = None;
+                }
+            }
+            KeyCode::Escape => {
+                self.inspector = None;
+                self.poincare = None;
+            }
+            KeyCode::P => {
+                if let (Some(inspector), Family::Fractal(f)) = (&self.inspector, &self.pendulums) {
+                    let (params, start) = inspector.start();
+                    let config = f.config();
+                    let section = Section::new(params, start, pendulum::STEP_DELTA);
+                    let integrator = config.integrator.build(config.atol, config.rtol);
+                    self.poincare = Some(PoincareView::new(section, integrator, self.section.1));
+                }
+            }
+            KeyCode::C => {
+                if let Some(poincare) = &self.poincare {
+                    let (section, path) = (poincare.section(), &self.section.0);
+                    match section.save_csv(path) {
+                        Ok(()) => println!("poincare: {} points saved to {}", section.points.len(), path.display()),
+                        Err(e) => eprintln!("poincare: {}: {}", path.display(), e),
+                    }
+                }
+            }
+            KeyCode::S => {
+                if let Family::Fractal(f) = &self.pendulums {
+                    self.autosave.save(f);
+                }
             }
             KeyCode::Space => {
-                if self.state == GameState::RUN {
+                if self.state == GameState::Run {
                     println!("===PAUSE===");
-                    self.state = GameState::PAUSE;
+                    self.state = GameState::Pause;
                 }
-                else if self.state == GameState::PAUSE {
+                else if self.state == GameState::Pause {
                     println!("===RUN===");
-                    self.state = GameState::RUN;
+                    self.state = GameState::Run;
                 }
             }
             _ => {}
@@ -246,34 +553,62 @@ impl EventHandler for MyGame {
 
     fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) {
         self.hint.pos = vec2(x, y);
+        self.hint.moved = true;
+        if let Some((_, end)) = &mut self.drag {
+            *end = vec2(x, y);
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: event::MouseButton, x: f32, y: f32) {
+        let (start, end) = match self.drag.take() {
+            Some((start, _)) if button == event::MouseButton::Left => (start, vec2(x, y)),
+            _ => return,
+        };
+        // a click, not a box
+        if (end - start).abs().min_element() < 4.0 {
+            self.inspect(x as f64, y as f64);
+            return;
+        }
+        if let Family::Fractal(f) = &self.pendulums {
+            let dims = f.dims();
+            let region = f.config().region.sub_fit(frac(start, dims), frac(end, dims));
+            self.zoom(region);
+        }
     }
 
     fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: event::MouseButton, x: f32, y: f32) {
-        let p = self.pendulums.find_all(x as f64, y as f64);
-        if let Some(pref) = p {
+        if button == event::MouseButton::Left {
+            self.drag = Some((vec2(x, y), vec2(x, y)));
+        }
+        let pendulums = match &mut self.pendulums {
+            Family::Fractal(f) => f,
+            Family::Grid(_) => return,
+        };
+        let p = pendulums.find_all(x as f64, y as f64);
+        if let Some(p) = p {
             let stopped;
             {
-                let p = pref.borrow();
                 println!(
-                    "[{}] ({}, {}) sc={} st={} {} dive={} run={} ngs={:?}",
+                    "[{}] ({}, {}) sc={} st={} {} t={:.2} dive={} run={} ngs={:?}",
                     p.id,
                     p.p.x,
                     p.p.y,
                     p.scale,
-                    p.stopped,
-                    p.steps,
-                    self.pendulums.dive.contains(&p.id),
-                    self.pendulums.ps.contains_key(&p.id),
+                    p.motion.stopped,
+                    p.motion.steps,
+                    p.motion.t,
+                    pendulums.dive.contains(&p.id),
+                    pendulums.ps.binary_search(&p.id).is_ok(),
                     p.neighbors,
                 );
-                stopped = p.stopped;
+                stopped = p.motion.stopped;
             }
 
             if stopped && button == event::MouseButton::Left {
                 // let mut ps = vec![pref.clone()];
                 // let next = self.pendulums.dive_all(&mut ps);
                 // self.pendulums.ps.extend(next);
-                // self.state = GameState::RUN;
+                // self.state = GameState::Run;
             }
         }
     }
@@ -1,37 +1,43 @@
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet, VecDeque};
 use std::f64::consts::{PI, TAU};
-use std::rc::Rc;
-use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
-
-use angular_units::{Angle, Deg, Rad};
-use crossbeam::channel::bounded;
-use ggez::graphics::{self, get_window_color_format, Canvas, Color, DrawMode, FillOptions, Rect};
+use std::time::Instant;
+
+use angular_units::{Angle, Rad};
+use ggez::graphics::{self, Color, DrawMode, Rect};
 use ggez::*;
 use glam::*;
-use prisma::Lerp;
+use serde::{Deserialize, Serialize};
+
+use rayon::prelude::*;
 
-use crate::avgspeed::RollingAverage;
+use crate::batch::{self, Stop, LANES};
+use crate::pendulum::Motion;
+use crate::physics::{Equations, Integrator, IntegratorKind, Params, State, G};
 
-const G: f64 = 9.81;
 const L1: f64 = 80.0;
 
 // dt for physics
 const STEP_DELTA: f64 = 0.01;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    // the min angle of first arm
-    pub xmin:         f64,
-    // misnomer. this is the with of the area in terms of width
-    pub xmax:         f64,
-    pub ymin:         f64,
-    pub ymax:         f64,
     pub min_pixel:    f64,
     pub update_steps: usize,
+    pub integrator:   IntegratorKind,
+    pub atol:         f64,
+    pub rtol:         f64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            min_pixel:    8.0,
+            update_steps: 10,
+            integrator:   IntegratorKind::Euler,
+            atol:         1e-8,
+            rtol:         1e-8,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -44,35 +50,36 @@ pub struct DoublePendulum {
     pub l2:     f64,   // len of snd arm
     pub dt1:    f64,   //
     pub dt2:    f64,   //
-    pub d2t1:   f64,
-    pub d2t2:   f64,
     pub scale:  f64,
 
     pub steps: usize,
+    // simulated time, seconds
+    pub t:     f64,
+    // size of the next step, changed by adaptive integrators
+    h:         f64,
     color:     Color,
 }
 
 impl DoublePendulum {
     pub fn new(p: DVec2, theta1: f64, theta2: f64, scale: f64) -> Self {
-        let this = DoublePendulum {
+        DoublePendulum {
             id:     0,
-            p:      p,
-            theta1: theta1,
-            theta2: theta2,
+            p,
+            theta1,
+            theta2,
             l1:     L1,
             l2:     L1,
             dt1:    0.0,
             dt2:    0.0,
-            d2t1:   0.0,
-            d2t2:   0.0,
-            scale:  scale,
+            scale,
             steps:  0,
+            t:      0.0,
+            h:      STEP_DELTA,
             color:  Color::WHITE,
-        };
-        this
+        }
     }
 
-    pub fn new2(p: DVec2, width: f64, scale: f64, config: &Config) -> Self {
+    pub fn new2(p: DVec2, dims: DVec2, scale: f64, config: &Config) -> Self {
         // p.x .. w => 0 .. TAU
         // p.y .. w => 0 .. PI
         //let theta1 = config.xmin + p.x / width * config.xmax;
@@ -80,59 +87,67 @@ impl DoublePendulum {
         let theta1 = PI;
         let theta2 = PI / 2.0;
         let mut this = DoublePendulum::new(p, theta1, theta2, scale);
-        this.l1 = 10.0 * ( p.x / width + 1.0);
-        this.l2 = 10.0 * ( p.y / width + 1.0);
+        this.l1 = 10.0 * ( p.x / dims.x + 1.0);
+        this.l2 = 10.0 * ( p.y / dims.y + 1.0);
         this
     }
 
-    pub fn update(&mut self, update_steps: usize) -> GameResult<()> {
+    pub fn update(&mut self, update_steps: usize, integrator: &dyn Integrator) -> GameResult<()> {
         for _ in 1..=update_steps {
-            self.step(STEP_DELTA);
+            self.step(integrator);
         }
 
         Ok(())
     }
 
-    fn step(&mut self, delta: f64) {
+    /// the grid varies the lengths and keeps the masses equal to them, with the baseline equations, as it
+    /// always did
+    fn params(&self) -> Params {
+        Params { l1: self.l1, l2: self.l2, m1: self.l1, m2: self.l2, g: G, equations: Equations::Baseline }
+    }
+
+    fn motion(&self) -> Motion {
+        Motion {
+            params:  self.params(),
+            state:   State { theta1: self.theta1, theta2: self.theta2, dt1: self.dt1, dt2: self.dt2 },
+            stopped: false,
+            expired: false,
+            never:   false,
+            steps:   self.steps,
+            t:       self.t,
+            flip_t:  0.0,
+            h:       self.h,
+            prev:    self.theta2,
+            flips:   0,
+            flip:    None,
+            shadow:  State { theta1: self.theta1, theta2: self.theta2, dt1: self.dt1, dt2: self.dt2 },
+            lyap:    0.0,
+            e0:      0.0,
+            drift:   0.0,
+        }
+    }
+
+    fn set_motion(&mut self, m: Motion) {
+        (self.theta1, self.theta2, self.dt1, self.dt2) = (m.state.theta1, m.state.theta2, m.state.dt1, m.state.dt2);
+        self.steps = m.steps;
+        self.t = m.t;
+    }
+
+    fn step(&mut self, integrator: &dyn Integrator) {
         self.steps += 1;
 
-        let a = 2.0 * self.l1 + self.l2 - self.l2 * (2.0 * self.theta1 - 2.0 * self.theta2).cos();
-
-        self.d2t1 = (-G * (2.0 * self.l1 + self.l2) * self.theta1.sin()
-            - self.l2 * G * (self.theta1 - 2.0 * self.theta2).sin()
-            - 2.0
-                * (self.theta1 - self.theta2).sin()
-                * self.l2
-                * (self.dt2 * self.dt2 * self.l2 - self.dt1 * self.dt1 * self.l1 * (self.theta1 - self.theta2).cos()))
-            / (self.l1 * a);
-
-        self.d2t2 = (2.0
-            * (self.theta1 - self.theta2).sin()
-            * (self.dt1 * self.dt1 * self.l1 * (self.l1 + self.l2)
-                + G * (self.l1 + self.l2) * self.theta1.cos()
-                + self.dt2 * self.dt2 * self.l2 * self.l2 * (self.theta1 - self.theta2).cos()))
-            / (self.l2 * a);
-
-        if self.d2t1.is_nan()
-            || self.dt1.is_nan()
-            || self.theta1.is_nan()
-            || self.d2t2.is_nan()
-            || self.dt2.is_nan()
-            || self.theta2.is_nan()
-        {
+        let mut s = State { theta1: self.theta1, theta2: self.theta2, dt1: self.dt1, dt2: self.dt2 };
+        self.t += integrator.step(&self.params(), &mut s, &mut self.h);
+        (self.theta1, self.theta2, self.dt1, self.dt2) = (s.theta1, s.theta2, s.dt1, s.dt2);
+
+        if self.dt1.is_nan() || self.theta1.is_nan() || self.dt2.is_nan() || self.theta2.is_nan() {
             println!(
-                "{} {} {} {} {} {} {} {}",
-                self.l1, self.l2, self.theta1, self.dt1, self.d2t1, self.theta2, self.dt2, self.d2t2
+                "{} {} {} {} {} {} t={}",
+                self.l1, self.l2, self.theta1, self.dt1, self.theta2, self.dt2, self.t
             );
             panic!("");
         }
 
-        self.dt1 += self.d2t1 * delta;
-        self.dt2 += self.d2t2 * delta;
-
-        self.theta1 += self.dt1 * delta;
-        self.theta2 += self.dt2 * delta;
-
         // while self.theta1 > TAU {
         //     self.theta1 -= TAU
         // }
@@ -164,8 +179,8 @@ impl DoublePendulum {
         Rect::new(x1 as f32, y1 as f32, w as f32, h as f32)
     }
 
-    pub fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let w = self.scale * 2048.0;
+    pub fn draw(&mut self, ctx: &mut Context, width: f64) -> GameResult<()> {
+        let w = self.scale * width;
         let draw_mode = DrawMode::fill();
         let color = self.color();
         let mb = &mut graphics::MeshBuilder::new();
@@ -184,7 +199,7 @@ impl DoublePendulum {
 
 pub struct PendulumFamily2 {
     config:           Config,
-    width:            f64,
+    dims:             DVec2,
     pub ps:           Vec<DoublePendulum>,
     pub iter:         usize,
     pub update_steps: usize,
@@ -192,10 +207,10 @@ pub struct PendulumFamily2 {
 }
 
 impl PendulumFamily2 {
-    pub fn new(ctx: &mut Context, config: Config, width: f64) -> Self {
+    pub fn new(config: Config, dims: DVec2) -> Self {
         Self {
-            config:       config,
-            width:        width,
+            config,
+            dims,
             ps:           Vec::new(),
             iter:         0,
             update_steps: 100,
@@ -204,13 +219,13 @@ impl PendulumFamily2 {
     }
 
     pub fn init(&mut self) {
-        let cnt = (self.width / self.config.min_pixel) as usize;
-        for i in 0..cnt {
-            for j in 0..cnt {
+        let (nx, ny) = ((self.dims.x / self.config.min_pixel) as usize, (self.dims.y / self.config.min_pixel) as usize);
+        for i in 0..nx {
+            for j in 0..ny {
                 self.ps.push(DoublePendulum::new2(
                     dvec2(i as f64 * self.config.min_pixel, j as f64 * self.config.min_pixel),
-                    self.width,
-                    self.config.min_pixel / self.width,
+                    self.dims,
+                    self.config.min_pixel / self.dims.x,
                     &self.config,
                 ));
             }
@@ -222,11 +237,24 @@ impl PendulumFamily2 {
         self.ps.len()
     }
 
-    pub fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+    pub fn update(&mut self) -> GameResult<()> {
         self.iter += 1;
         let update_steps = self.config.update_steps;
-        for p in &mut self.ps {
-            p.update(update_steps)?;
+        if self.config.integrator == IntegratorKind::Euler {
+            // SIMD, a few pendulums at a time. the grid keeps running through the flips
+            self.ps.par_chunks_mut(LANES).for_each(|c| {
+                let mut ms = c.iter().map(|p| p.motion()).collect::<Vec<_>>();
+                batch::update(&mut ms, update_steps, usize::MAX, Stop::RunThrough);
+                for (p, m) in c.iter_mut().zip(ms) {
+                    p.set_motion(m);
+                }
+            });
+        }
+        else {
+            let integrator = self.config.integrator.build(self.config.atol, self.config.rtol);
+            for p in &mut self.ps {
+                p.update(update_steps, &*integrator)?;
+            }
         }
         println!("{} {:?}", self.iter, self.t.elapsed());
         self.t = Instant::now();
@@ -236,7 +264,7 @@ impl PendulumFamily2 {
     pub fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         let t = Instant::now();
         for p in &mut self.ps {
-            p.draw(ctx)?
+            p.draw(ctx, self.dims.x)?
         }
         println!("draw: {:?}", t.elapsed());
         Ok(())
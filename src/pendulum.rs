@@ -1,617 +1,882 @@
-use std::cell::RefCell;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::f64::consts::{PI, TAU};
-use std::rc::Rc;
-use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
-
-use angular_units::{Angle, Deg, Rad};
-use crossbeam::channel::bounded;
-use ggez::graphics::{self, get_window_color_format, Canvas, Color, DrawMode, FillOptions, Rect};
+use std::time::Instant;
+
+use ggez::graphics::{self, Canvas, Color, DrawMode, Rect};
 use ggez::*;
+use clap::ValueEnum;
 use glam::*;
-use prisma::Lerp;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use crate::arena::Arena;
 use crate::avgspeed::RollingAverage;
+use crate::field::{self, FlipField};
+use crate::lyapunov;
+use crate::physics::{energy, energy_scale, Equations, Integrator, IntegratorKind, Params, Quantity, State, G};
+use crate::policy::{ColorBy, PendulumPolicy, PolicyKind};
+use crate::raster::Raster;
+use crate::region::Region;
 
-const G: f64 = 9.81;
 const L1: f64 = 80.0;
 
 // dt for physics
-const STEP_DELTA: f64 = 0.01;
+pub const STEP_DELTA: f64 = 0.01;
+
+// keep away from the energy threshold by this fraction of the energy scale, the integrators do not
+// conserve the energy exactly
+const FLIP_ENERGY_MARGIN: f64 = 1e-3;
+
+// mirror images flip at the same time up to the rounding of the flip interpolation
+const MIRROR_TOLERANCE: f64 = 1e-9;
+
+/// how the moment of the flip is found inside the step where theta2 crossed ±PI
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlipInterp {
+    /// end of the step, the flip time is a whole number of steps (the baseline colors)
+    #[default]
+    None,
+    /// straight line between the two angles
+    Linear,
+    /// cubic through the two angles with dt2 as the slopes
+    Hermite,
+}
+
+/// use of the mirror symmetry of the picture, see `Config::mirrored`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Symmetry {
+    /// compute every cell
+    Off,
+    /// compute the upper half, the cells of the lower half copy the flip of their mirror image
+    #[default]
+    Mirror,
+    /// compute both halves and report the cells that differ from their mirror image
+    Check,
+}
+
+/// an arm of the pendulum
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Arm {
+    First,
+    Second,
+}
+
+/// an arm over the top
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Flip {
+    pub arm:       Arm,
+    // the angle grew through the top, dt > 0
+    pub clockwise: bool,
+}
+
+impl fmt::Display for Flip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arm = match self.arm {
+            Arm::First => "first",
+            Arm::Second => "second",
+        };
+        write!(f, "{} arm {}", arm, if self.clockwise { "clockwise" } else { "counter-clockwise" })
+    }
+}
 
-#[derive(Clone)]
+/// fraction 0..1 of a step of `h` seconds at which theta2 went from `y0` to `y1` across `level`.
+/// `m0`, `m1` are dt2 at both ends
+pub(crate) fn flip_fraction(interp: FlipInterp, level: f64, (y0, m0): (f64, f64), (y1, m1): (f64, f64), h: f64) -> f64 {
+    match interp {
+        FlipInterp::None => 1.0,
+        FlipInterp::Linear => ((level - y0) / (y1 - y0)).clamp(0.0, 1.0),
+        FlipInterp::Hermite => {
+            let y = |s: f64| {
+                let (s2, s3) = (s * s, s * s * s);
+                (2.0 * s3 - 3.0 * s2 + 1.0) * y0
+                    + (s3 - 2.0 * s2 + s) * h * m0
+                    + (-2.0 * s3 + 3.0 * s2) * y1
+                    + (s3 - s2) * h * m1
+            };
+            // the ends are on the opposite sides of level, bisect
+            let (mut a, mut b) = (0.0, 1.0);
+            let below = y0 < level;
+            for _ in 0..50 {
+                let mid = (a + b) / 2.0;
+                if (y(mid) < level) == below {
+                    a = mid;
+                }
+                else {
+                    b = mid;
+                }
+            }
+            (a + b) / 2.0
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    // the min angle of first arm
-    pub xmin:       f64,
-    // misnomer. this is the with of the area in terms of width
-    pub xmax:       f64,
-    pub ymin:       f64,
-    pub ymax:       f64,
+    // what the x and y axes change, theta1 and theta2 for the classic picture
+    pub x_axis:      Quantity,
+    pub y_axis:      Quantity,
+    // bounds of the picture in terms of x_axis and y_axis
+    pub region:      Region,
     // just play with this values for better color
-    pub color_step: f64,
-    pub color_mod:  usize,
+    pub color_step:  f64,
+    pub color_mod:   usize,
     // if two adjacent pendums differ more than this - split them both. 0..1
-    pub dive_diff:  f64,
+    pub dive_diff:   f64,
     // max step before pendulum is expired. divided by scale
-    pub max_step:   usize,
+    pub max_step:    usize,
     //
-    pub min_pixel:  f64,
+    pub min_pixel:   f64,
     // adjust the speed of phys iter per frame
-    pub speed_a:    f64,
-    pub speed_b:    f64,
+    pub speed_a:     f64,
+    pub speed_b:     f64,
+    // numerical scheme for the physics, and the equations it solves
+    pub integrator:  IntegratorKind,
+    pub equations:   Equations,
+    // error tolerances of the adaptive integrator
+    pub atol:        f64,
+    pub rtol:        f64,
+    // what stops a cell and colors it
+    pub policy:      PolicyKind,
+    // stop at this flip of the watched arms, 1 for the first one
+    pub flip_count:  u32,
+    // what the color of a stopped cell shows
+    pub color_by:    ColorBy,
+    // seconds every cell runs for with the lyapunov policy, and the exponent of the brightest color, 1/s
+    pub lyap_time:   f64,
+    pub lyap_scale:  f64,
+    // sub-step estimate of the flip time, for smooth colors
+    pub flip_interp: FlipInterp,
+    // stop the cells without the energy to flip before running them
+    pub skip_never:  bool,
+    // halve the work of the pictures that are their own mirror image
+    pub symmetry:    Symmetry,
+    // the start of every pendulum, except for the quantities on the axes
+    pub theta1:      f64,
+    pub theta2:      f64,
+    pub dt1:         f64,
+    pub dt2:         f64,
+    // unset arms are a quarter of the width of the picture
+    pub l1:          Option<f64>,
+    pub l2:          Option<f64>,
+    pub m1:          f64,
+    pub m2:          f64,
+    pub g:           f64,
 }
 
-#[derive(Clone, Debug)]
-pub struct DoublePendulum {
-    pub id:    usize,
-    childs:    Vec<usize>,
-    parent_id: usize,
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            x_axis:      Quantity::Theta1,
+            y_axis:      Quantity::Theta2,
+            region:      Region::new(0.0, TAU, 0.0, PI),
+            color_step:  100.0,
+            dive_diff:   0.82,
+            max_step:    460_000,
+            min_pixel:   4.0,
+            color_mod:   6000,
+            speed_a:     550.0,
+            speed_b:     20.0,
+            integrator:  IntegratorKind::Euler,
+            equations:   Equations::Baseline,
+            atol:        1e-8,
+            rtol:        1e-8,
+            policy:      PolicyKind::Flip,
+            flip_count:  1,
+            color_by:    ColorBy::Time,
+            lyap_time:   300.0,
+            lyap_scale:  0.1,
+            flip_interp: FlipInterp::None,
+            skip_never:  true,
+            symmetry:    Symmetry::Mirror,
+            theta1:      0.0,
+            theta2:      0.0,
+            dt1:         0.0,
+            dt2:         0.0,
+            l1:          None,
+            l2:          None,
+            m1:          1.0,
+            m2:          1.0,
+            g:           G,
+        }
+    }
+}
 
-    pub p:         DVec2, // fixed point
-    pub theta1:    f64,   // angle of first arm: 0 .. TAU
-    pub theta2:    f64,   // angle of snd arm: 0 .. TAU
-    pub l1:        f64,   // len of first arm
-    pub l2:        f64,   // len of snd arm
-    pub dt1:       f64,   //
-    pub dt2:       f64,   //
-    pub d2t1:      f64,
-    pub d2t2:      f64,
-    pub scale:     f64,
-    pub neighbors: Vec<usize>,
+impl Config {
+    /// physics of the pendulum at (fx, fy) of a picture `width` pixels wide, fx and fy both 0..1
+    pub fn start(&self, fx: f64, fy: f64, width: f64) -> (Params, State) {
+        self.start_at(self.region.at(fx, fy), width)
+    }
+
+    /// physics of the pendulum with the axis quantities `v`
+    pub fn start_at(&self, v: DVec2, width: f64) -> (Params, State) {
+        let (l1, l2) = self.arms(width);
+        let mut p = Params { l1, l2, m1: self.m1, m2: self.m2, g: self.g, equations: self.equations };
+        let mut s = State { theta1: self.theta1, theta2: self.theta2, dt1: self.dt1, dt2: self.dt2 };
+        self.x_axis.set(&mut p, &mut s, v.x);
+        self.y_axis.set(&mut p, &mut s, v.y);
+        (p, s)
+    }
+
+    /// the arm lengths in a picture `width` pixels wide
+    pub fn arms(&self, width: f64) -> (f64, f64) {
+        (self.l1.unwrap_or(width / 4.0), self.l2.unwrap_or(width / 4.0))
+    }
+
+    /// the stop condition and the colors of the cells
+    pub fn policy(&self) -> Box<dyn PendulumPolicy> {
+        self.policy.build(self)
+    }
 
+    /// whether the pixel `dims - p` is the mirror image of the pixel `p`. at rest the pendulum
+    /// (-theta1, -theta2) is the mirror image of (theta1, theta2) and flips at the same time, so the
+    /// axes must be angles or speeds, the rest of the start must be its own mirror image and the
+    /// region must be centered on zero (theta1 modulo TAU)
+    pub fn mirrored(&self) -> bool {
+        let axes = [self.x_axis, self.y_axis];
+        let state = [
+            (Quantity::Theta1, self.theta1),
+            (Quantity::Theta2, self.theta2),
+            (Quantity::Dt1, self.dt1),
+            (Quantity::Dt2, self.dt2),
+        ];
+        // -a is b, theta1 goes around
+        let mirror = |q: Quantity, a: f64, b: f64, eps: f64| {
+            let period = if q == Quantity::Theta1 { TAU } else { f64::INFINITY };
+            let sum = (a + b) % period;
+            sum.abs() <= eps || (period - sum.abs()).abs() <= eps
+        };
+        let r = &self.region;
+        axes.iter().all(|q| state.iter().any(|(s, _)| s == q))
+            && state.iter().all(|(q, v)| axes.contains(q) || mirror(*q, *v, *v, 0.0))
+            && mirror(self.x_axis, r.xmin, r.xmax, 1e-12 * r.width())
+            && mirror(self.y_axis, r.ymin, r.ymax, 1e-12 * r.height())
+    }
+}
+
+/// the physics of one pendulum, everything that changes while it runs
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Motion {
+    pub params:  Params,
+    pub state:   State,
     pub stopped: bool,
+    pub expired: bool,
+    // stopped before the start, it has not the energy to flip
+    pub never:   bool,
     pub steps:   usize,
+    // simulated time, seconds
+    pub t:       f64,
+    // interpolated time of the flip, seconds. t for the expired ones
+    pub flip_t:  f64,
+    // size of the next step, changed by adaptive integrators
+    pub h:       f64,
+    // theta2 before the last step
     pub prev:    f64,
-    color:       Color,
-    expired:     bool,
+    // flips so far, and the last one
+    pub flips:   u32,
+    pub flip:    Option<Flip>,
+    // the nearby start for the lyapunov exponent, and the log of its growth so far
+    pub shadow:  State,
+    pub lyap:    f64,
+    // energy at the start, and its change until the stop relative to the energy scale
+    pub e0:      f64,
+    pub drift:   f64,
 }
 
-impl DoublePendulum {
-    pub fn new(p: DVec2, theta1: f64, theta2: f64, scale: f64) -> Self {
-        let this = DoublePendulum {
-            id:        0,
-            parent_id: 0,
-            childs:    Vec::new(),
-            p:         p,
-            theta1:    theta1,
-            theta2:    theta2,
-            l1:        L1,
-            l2:        L1,
-            dt1:       0.0,
-            dt2:       0.0,
-            d2t1:      0.0,
-            d2t2:      0.0,
-            scale:     scale,
-            neighbors: Vec::new(),
-            stopped:   false,
-            steps:     0,
-            prev:      f64::INFINITY,
-            color:     Color::WHITE,
-            expired:   false,
-        };
-        this
-    }
-
-    pub fn new2(p: DVec2, width: f64, scale: f64, config: &Config) -> Self {
-        // p.x .. w => 0 .. TAU
-        // p.y .. w => 0 .. PI
-        //let theta1 = p.x / width * TAU;
-        //let theta2 = p.y / width * PI;
-        // (480, 1056)
-        let theta1 = config.xmin + p.x / width * config.xmax;
-        let theta2 = config.ymin + p.y / width * config.ymax;
-        let mut this = DoublePendulum::new(p, theta1, theta2, scale);
-        this.l1 = width / 4.0 * 1.0;
-        this.l2 = width / 4.0 * 1.0;
-        this
+impl Motion {
+    /// the start of a run, before the first step
+    pub fn new(params: Params, state: State) -> Self {
+        Motion {
+            params,
+            state,
+            stopped: false,
+            expired: false,
+            never:   false,
+            steps:   0,
+            t:       0.0,
+            flip_t:  0.0,
+            h:       STEP_DELTA,
+            prev:    f64::INFINITY,
+            flips:   0,
+            flip:    None,
+            // the lyapunov policy places it on the first step
+            shadow:  State { dt1: 0.0, dt2: 0.0, ..state },
+            lyap:    0.0,
+            e0:      0.0,
+            drift:   0.0,
+        }
     }
 
-    pub fn split(&mut self, width: f64, min_pixel: f64, config: &Config) -> Vec<DoublePendulum> {
-        assert!(self.stopped);
-        if self.width() < min_pixel {
-            return vec![];
-        }
-        let mut res = Vec::new();
-        let d = self.width() / 2.0;
-        if self.p.x - d > 0.0 && self.p.y - d > 0.0 {
-            res.push(DoublePendulum::new2(
-                dvec2(self.p.x - d, self.p.y - d),
-                width,
-                self.scale / 2.0,
-                config,
-            ))
-        }
-        if self.p.x - d > 0.0 && self.p.y + d < width {
-            res.push(DoublePendulum::new2(
-                dvec2(self.p.x - d, self.p.y + d),
-                width,
-                self.scale / 2.0,
-                config,
-            ))
-        }
-        if self.p.x + d < width && self.p.y - d > 0.0 {
-            res.push(DoublePendulum::new2(
-                dvec2(self.p.x + d, self.p.y - d),
-                width,
-                self.scale / 2.0,
-                config,
-            ))
-        }
-        if self.p.x + d < width && self.p.y + d < width {
-            res.push(DoublePendulum::new2(
-                dvec2(self.p.x + d, self.p.y + d),
-                width,
-                self.scale / 2.0,
-                config,
-            ))
-        }
-
-        for r in &mut res {
-            self.childs.push(r.id);
-            r.color = self.color;
+    /// the same run for the mirror image (-theta1, -theta2, -dt1, -dt2), it flips at the same time
+    /// the other way round
+    pub(crate) fn mirrored(&self) -> Motion {
+        let neg = |s: State| State { theta1: -s.theta1, theta2: -s.theta2, dt1: -s.dt1, dt2: -s.dt2 };
+        Motion {
+            state: neg(self.state),
+            prev: -self.prev,
+            shadow: neg(self.shadow),
+            flip: self.flip.map(|f| Flip { clockwise: !f.clockwise, ..f }),
+            ..*self
         }
-
-        res
     }
 
-    pub fn update(&mut self, update_steps: usize, max_step: usize) -> GameResult<()> {
+    pub fn update(&mut self, update_steps: usize, max_step: usize, integrator: &dyn Integrator, policy: &dyn PendulumPolicy) {
         for _ in 1..=update_steps {
-            self.step(STEP_DELTA, max_step);
+            self.step(integrator, max_step, policy);
         }
+    }
 
-        Ok(())
+    /// simulated time in units of STEP_DELTA, the same as the step count for fixed step integrators
+    pub fn ticks(&self) -> usize {
+        (self.t / STEP_DELTA).round() as usize
     }
 
-    fn step(&mut self, delta: f64, max_step: usize) {
+    fn step(&mut self, integrator: &dyn Integrator, max_step: usize, policy: &dyn PendulumPolicy) {
         if self.stopped {
             return;
         }
         // if self.steps >= 8000 + (max_step as f64 / self.scale) as usize {
-        if self.steps >= max_step {
+        // max_step is in units of STEP_DELTA, so adaptive integrators get the same simulated time
+        if self.ticks() >= max_step {
             self.stopped = true;
             self.expired = true;
+            self.flip_t = self.t;
+            self.drift = self.energy_drift();
             return;
         }
         self.steps += 1;
 
-        let a = 2.0 * self.l1 + self.l2 - self.l2 * (2.0 * self.theta1 - 2.0 * self.theta2).cos();
-
-        self.d2t1 = (-G * (2.0 * self.l1 + self.l2) * self.theta1.sin()
-            - self.l2 * G * (self.theta1 - 2.0 * self.theta2).sin()
-            - 2.0
-                * (self.theta1 - self.theta2).sin()
-                * self.l2
-                * (self.dt2 * self.dt2 * self.l2 - self.dt1 * self.dt1 * self.l1 * (self.theta1 - self.theta2).cos()))
-            / (self.l1 * a);
-
-        self.d2t2 = (2.0
-            * (self.theta1 - self.theta2).sin()
-            * (self.dt1 * self.dt1 * self.l1 * (self.l1 + self.l2)
-                + G * (self.l1 + self.l2) * self.theta1.cos()
-                + self.dt2 * self.dt2 * self.l2 * self.l2 * (self.theta1 - self.theta2).cos()))
-            / (self.l2 * a);
-
-        self.dt1 += self.d2t1 * delta;
-        self.dt2 += self.d2t2 * delta;
-
-        self.theta1 += self.dt1 * delta;
-        self.theta2 += self.dt2 * delta;
-
-        // dt2 > 0 -> clockwise; dt2 < 0 - counter-cw
-        // -3.13 -> -3.15;  dt-
-        // 3.15 -> 3.13     dt-
-        // -3.15 -> -3.13;  dt+
-        // 3.13 -> 3.15;    dt+
-
-        if self.prev.is_finite() {
-            if (self.dt2 > 0.0 && ((self.theta2 < -PI && self.prev > -PI) || (self.theta2 > PI && self.prev < PI)))
-                || (self.dt2 < 0.0 && ((self.theta2 < -PI && self.prev > -PI) || (self.theta2 < PI && self.prev > PI)))
-            {
-                self.stopped = true;
-                // println!("stop = {}", self.steps);
-            }
-        }
-
-        self.prev = self.theta2;
-    }
-    fn update_color(&mut self, color_step: usize, color_mod: usize) {
-        if self.expired {
-            let cr = prisma::Rgb::new(0.7, 0.7, 0.7);
-            self.color = (cr.red(), cr.green(), cr.blue()).into();
+        let before = self.state;
+        if self.steps == 1 {
+            self.e0 = energy(&self.params, &before);
         }
-        else {
-            let p = (self.steps * color_step % color_mod) as f64 / (color_mod as f64) * TAU;
-            let c = prisma::Hsv::new(Rad::new(p), 1.0, 1.0);
-            let cr: prisma::Rgb<f32> = c.into();
-            self.color = (cr.red(), cr.green(), cr.blue()).into();
+        let dt = integrator.step(&self.params, &mut self.state, &mut self.h);
+        self.t += dt;
+        self.stopped = policy.stop(self, &before, dt);
+        if self.stopped {
+            self.drift = self.energy_drift();
         }
     }
 
-    fn color(&mut self) -> Color {
-        self.color
+    /// change of the energy since the start, relative to `physics::energy_scale`. the exact motion of
+    /// the lagrange equations keeps it, so there this is the error of the integrator
+    pub fn energy_drift(&self) -> f64 {
+        (energy(&self.params, &self.state) - self.e0) / energy_scale(&self.params)
     }
 
-    fn pcolor(&self) -> Color {
-        let mut p = self.theta2.abs();
-        while p > TAU {
-            p -= TAU
-        }
-        let cr: prisma::Rgb<f32> = prisma::Hsv::new(Rad::new(p), 0.5, 1.0).into();
-        (cr.red(), cr.green(), cr.blue()).into()
+    /// the energy is too low to ever reach `threshold`, see `physics::flip_energy`
+    pub fn below(&self, threshold: f64) -> bool {
+        let p = &self.params;
+        energy(p, &self.state) < threshold - FLIP_ENERGY_MARGIN * energy_scale(p)
     }
+}
 
-    #[inline(always)]
-    fn rect(&self) -> Rect {
-        let w = (self.scale * (self.l1 + self.l2)) as f32;
-        Rect::new(self.p.x as f32 - w, self.p.y as f32 - w as f32, 2.0 * w, 2.0 * w)
-    }
+#[derive(Clone, Debug)]
+pub struct DoublePendulum {
+    pub id:               usize,
+    pub(crate) childs:    Vec<usize>,
+    pub(crate) parent_id: usize,
 
-    pub fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let draw_mode = DrawMode::fill();
-        let color = self.color();
-        let mb = &mut graphics::MeshBuilder::new();
-        if self.stopped {
-            mb.rectangle(draw_mode, self.rect(), color)?;
+    pub p:         DVec2, // fixed point
+    pub size:      DVec2, // half of the sides of the cell at scale 1, pixels
+    pub scale:     f64,
+    pub neighbors: Vec<usize>,
+    pub motion:    Motion,
+    pub(crate) color: Color,
+}
+
+impl DoublePendulum {
+    pub fn new(p: DVec2, theta1: f64, theta2: f64, scale: f64) -> Self {
+        let params = Params { l1: L1, l2: L1, m1: 1.0, m2: 1.0, g: G, equations: Equations::Baseline };
+        DoublePendulum {
+            id:        0,
+            parent_id: 0,
+            childs:    Vec::new(),
+            p,
+            size:      dvec2(2.0 * L1, 2.0 * L1),
+            scale,
+            neighbors: Vec::new(),
+            motion:    Motion::new(params, State { theta1, theta2, dt1: 0.0, dt2: 0.0 }),
+            color:     Color::WHITE,
         }
-        else {
-            let pcolor = self.pcolor();
-            let mut p1 = self.p.clone();
-            let (ts1, tc1) = self.theta1.sin_cos();
-            p1.x += self.scale * ts1 * self.l1;
-            p1.y += self.scale * tc1 * self.l1;
+    }
 
-            let mut p2 = p1.clone();
-            let (ts2, tc2) = self.theta2.sin_cos();
-            p2.x += self.scale * ts2 * self.l2;
-            p2.y += self.scale * tc2 * self.l2;
+    /// the pendulum at the pixel `p` of a window of `dims` pixels
+    pub fn new2(p: DVec2, dims: DVec2, scale: f64, config: &Config) -> Self {
+        // p.x .. w => 0 .. TAU
+        // p.y .. w => 0 .. PI
+        //let theta1 = p.x / width * TAU;
+        //let theta2 = p.y / width * PI;
+        // (480, 1056)
+        let (params, state) = config.start(p.x / dims.x, p.y / dims.y, dims.x);
+        let mut this = DoublePendulum::new(p, state.theta1, state.theta2, scale);
+        (this.motion.params, this.motion.state) = (params, state);
+        this.size = dims / 2.0;
+        this
+    }
 
-            mb.rectangle(draw_mode, self.rect(), color)?;
-            mb.line(&[self.p.as_vec2(), p1.as_vec2(), p2.as_vec2()], 3.0, pcolor)?;
-        }
-        let mesh = mb.build(ctx)?;
-        graphics::draw(ctx, &mesh, (vec2(0.0, 0.0), Color::WHITE))?;
+    pub fn update(
+        &mut self,
+        update_steps: usize,
+        max_step: usize,
+        integrator: &dyn Integrator,
+        policy: &dyn PendulumPolicy,
+    ) -> GameResult<()> {
+        self.motion.update(update_steps, max_step, integrator, policy);
         Ok(())
     }
 
-    pub fn point_inside(&self, x: f64, y: f64) -> bool {
-        let w = (self.l1 + self.l2) * self.scale;
-        x > self.p.x - w && x < self.p.x + w && y > self.p.y - w && y < self.p.y + w
+    pub fn params(&self) -> Params {
+        self.motion.params
     }
 
-    fn width(&self) -> f64 {
-        (self.l1 + self.l2) * self.scale
+    pub fn state(&self) -> State {
+        self.motion.state
     }
 
-    fn adjacent(&self, other: &DoublePendulum) -> bool {
-        let epsilon = 0.1;
-        let w2 = self.width() + other.width();
-        let dx = (self.p.x - other.p.x).abs();
-        let dy = (self.p.y - other.p.y).abs();
-        // println!("{} {} {}", dx, dy, w2);
-        (dx <= w2 && (dy - w2).abs() < epsilon) || (dy <= w2 && (dx - w2).abs() < epsilon)
+    /// stopped because it did not flip in max_step
+    pub fn expired(&self) -> bool {
+        self.motion.expired
     }
-}
-pub trait PendulumPolicy {
-    fn stop(&mut self, p: &mut DoublePendulum, state: &PendulumFamily) -> bool;
-    fn color(&mut self, p: &mut DoublePendulum, state: &PendulumFamily) -> Color;
-    fn pcolor(&mut self, p: &mut DoublePendulum, state: &PendulumFamily) -> Color;
-}
 
-pub struct FlipPolicy {
-    prev: HashMap<usize, f64>,
+    /// finite-time lyapunov exponent of a cell stopped by `LyapunovPolicy`, 1/s
+    pub fn lyapunov(&self) -> f64 {
+        lyapunov::exponent(&self.motion)
+    }
 }
 
-impl FlipPolicy {
-    pub fn new() -> Self {
-        Self { prev: HashMap::new() }
-    }
+/// the center of a cell as a hash key, exact to 1/1024 pixel
+fn center_key(p: DVec2) -> (i64, i64) {
+    ((p.x * 1024.0).round() as i64, (p.y * 1024.0).round() as i64)
 }
 
-impl PendulumPolicy for FlipPolicy {
-    fn stop(&mut self, p: &mut DoublePendulum, state: &PendulumFamily) -> bool {
-        if p.steps >= (state.config.max_step as f64 / p.scale) as usize {
-            p.expired = true;
-            return true;
-        }
-        if let Some(prev) = self.prev.get(&p.id) {
-            if (p.dt2 > 0.0 && ((p.theta2 < -PI && p.prev > -PI) || (p.theta2 > PI && p.prev < PI)))
-                || (p.dt2 < 0.0 && ((p.theta2 < -PI && p.prev > -PI) || (p.theta2 < PI && p.prev > PI)))
-            {
-                return true;
-            }
-        }
-        self.prev.insert(p.id, p.theta2);
-        false
-    }
+/// the cell around `p` with half sides `half`, on the screen
+#[inline(always)]
+pub(crate) fn cell_rect(p: DVec2, half: DVec2) -> Rect {
+    let (w, h) = (half.x as f32, half.y as f32);
+    Rect::new(p.x as f32 - w, p.y as f32 - h, 2.0 * w, 2.0 * h)
+}
 
-    fn color(&mut self, p: &mut DoublePendulum, state: &PendulumFamily) -> Color {
-        todo!()
-    }
+pub(crate) fn cell_inside(p: DVec2, half: DVec2, x: f64, y: f64) -> bool {
+    x > p.x - half.x && x < p.x + half.x && y > p.y - half.y && y < p.y + half.y
+}
 
-    fn pcolor(&mut self, p: &mut DoublePendulum, state: &PendulumFamily) -> Color {
-        todo!()
-    }
+/// two cells, given as (center, half sides), share a piece of an edge
+pub(crate) fn cells_adjacent((a, ha): (DVec2, DVec2), (b, hb): (DVec2, DVec2)) -> bool {
+    let epsilon = 0.1;
+    let w2 = ha + hb;
+    let dx = (a.x - b.x).abs();
+    let dy = (a.y - b.y).abs();
+    // println!("{} {} {}", dx, dy, w2);
+    (dx <= w2.x && (dy - w2.y).abs() < epsilon) || (dy <= w2.y && (dx - w2.x).abs() < epsilon)
+}
+// a checkpoint has no policy, `restore` builds it again
+fn no_policy() -> Box<dyn PendulumPolicy> {
+    Config::default().policy()
 }
 
+/// the whole state of a render, a checkpoint saves and restores all of it but the window
+#[derive(Serialize, Deserialize)]
 pub struct PendulumFamily {
-    config:   Config,
-    width:    f64,
-    pub ps:   HashMap<usize, Rc<RefCell<DoublePendulum>>>,
-    pub done: HashMap<usize, Rc<RefCell<DoublePendulum>>>,
-    pub dive: HashSet<usize>,
-    to_draw:  VecDeque<Rc<RefCell<DoublePendulum>>>,
-    counter:  usize,
-    pub iter: usize,
-    canvas:   Canvas,
-    avg:      RollingAverage<u32>,
-    t:        Instant,
-
-    // policy:   Box<dyn PendulumPolicy>,
+    config:    Config,
+    // window size, pixels
+    dims:      DVec2,
+    // every cell ever made, the id of a cell is its index
+    pub cells: Arena,
+    // ids of the running cells, sorted
+    pub ps:    Vec<usize>,
+    pub dive:  HashSet<usize>,
+    // stopped cells that are not on the canvas yet
+    #[serde(skip)]
+    to_draw:   VecDeque<usize>,
+    // stop condition and colors, built from the config
+    #[serde(skip, default = "no_policy")]
+    policy:    Box<dyn PendulumPolicy>,
+    // cells stopped by the energy check
+    never:     usize,
+    // the picture is its own mirror image, see `Config::mirrored`
+    symmetric: bool,
+    // cell ids by their center, to find the mirror images
+    centers:   HashMap<(i64, i64), usize>,
+    // cells of the lower half that wait for their mirror image in the upper half to stop
+    twins:     HashMap<usize, usize>,
+    // cells that copied their mirror image
+    mirrored:  usize,
+    pub iter:  usize,
+    // created on first draw, so the family can run without a window
+    #[serde(skip)]
+    canvas:    Option<Canvas>,
+    avg:       RollingAverage<u32>,
+    #[serde(skip, default = "Instant::now")]
+    t:         Instant,
+
     pub update_steps: usize,
-    max_steps:        usize,
 }
 
 impl PendulumFamily {
-    pub fn new(ctx: &mut Context, config: Config, width: f64) -> Self {
+    pub fn new(config: Config, dims: DVec2) -> Self {
+        let symmetric = config.symmetry != Symmetry::Off && config.mirrored();
+        let policy = config.policy();
         PendulumFamily {
-            config:  config,
-            width:   width,
-            ps:      HashMap::new(),
-            done:    HashMap::new(),
-            counter: 0,
-            iter:    0,
-            dive:    HashSet::new(),
-            to_draw: VecDeque::new(),
-            canvas:  Canvas::with_window_size(ctx).unwrap(),
-            avg:     RollingAverage::new(1000),
-            t:       Instant::now(),
-
-            // policy:  policy,
+            config,
+            dims,
+            cells:     Arena::new(dims),
+            ps:        Vec::new(),
+            iter:      0,
+            dive:      HashSet::new(),
+            to_draw:   VecDeque::new(),
+            policy,
+            never:     0,
+            symmetric,
+            centers:   HashMap::new(),
+            twins:     HashMap::new(),
+            mirrored:  0,
+            canvas:    None,
+            avg:       RollingAverage::new(1000),
+            t:         Instant::now(),
+
             update_steps: 100,
-            max_steps:    2000,
         }
     }
 
-    fn next_id(&mut self) -> usize {
-        self.counter += 1;
-        self.counter
+    /// start with one pendulum that covers the whole screen
+    pub fn init(&mut self) {
+        let p = DoublePendulum::new2(self.dims / 2.0, self.dims, 1.0, &self.config);
+        let id = self.add(p, None);
+        self.ps.push(id);
     }
 
-    pub fn add(&mut self, mut p: DoublePendulum) {
-        p.id = self.next_id();
-        self.ps.insert(p.id, Rc::new(RefCell::new(p)));
+    /// store a new cell. the ones that can never flip are stopped before they run, unless the config says no
+    fn add(&mut self, p: DoublePendulum, parent: Option<usize>) -> usize {
+        let id = self.cells.insert(p, parent);
+        let check = self.checks_never();
+        let m = &mut self.cells.motion[id];
+        if check && self.policy.never(m) {
+            (m.stopped, m.never) = (true, true);
+            self.never += 1;
+        }
+        if self.symmetric {
+            self.add_twin(id);
+        }
+        id
     }
 
-    pub fn can_remove(&self, id: usize) -> bool {
-        let p = self.done.get(&id);
-        if p.is_none() {
-            return false;
+    /// let the cell `id` or its mirror image wait for the other one, whichever is in the lower half
+    fn add_twin(&mut self, id: usize) {
+        let p = self.cells.p[id];
+        self.centers.insert(center_key(p), id);
+        let Some(&other) = self.centers.get(&center_key(self.dims - p))
+        else {
+            return;
+        };
+        let (wait, src) = if p.y > self.dims.y / 2.0 { (id, other) } else { (other, id) };
+        let m = &self.cells.motion[wait];
+        // the waiting cell must not have run yet, the root is its own mirror image
+        if self.config.symmetry == Symmetry::Mirror && wait != src && !m.stopped && m.steps == 0 {
+            self.twins.insert(wait, src);
         }
-        let p = p.unwrap().borrow();
-        for c in &p.childs {
-            if let Some(c) = self.done.get(&c) {
-                if !c.borrow().stopped {
-                    return false;
-                }
+    }
+
+    /// copy the runs of the stopped cells into their waiting mirror images
+    fn copy_twins(&mut self) {
+        let done = self
+            .twins
+            .iter()
+            .filter(|(_, src)| self.cells.motion[**src].stopped)
+            .map(|(id, src)| (*id, *src))
+            .collect::<Vec<_>>();
+        for (id, src) in done {
+            self.cells.motion[id] = self.cells.motion[src].mirrored();
+            self.twins.remove(&id);
+            self.mirrored += 1;
+        }
+    }
+
+    /// how the mirror symmetry was used: the copied cells, or with `Symmetry::Check` the pairs of
+    /// mirror images that differ
+    pub fn symmetry_report(&self) -> String {
+        if !self.symmetric {
+            return "symmetry: the picture is not its own mirror image".into();
+        }
+        if self.config.symmetry == Symmetry::Mirror {
+            return format!("symmetry: {} of {} cells copied from their mirror image", self.mirrored, self.cells.len());
+        }
+        let (mut pairs, mut differ, mut max_diff) = (0, 0, 0.0f64);
+        for (key, &id) in &self.centers {
+            let p = self.cells.p[id];
+            let Some(&other) = self.centers.get(&center_key(self.dims - p))
+            else {
+                continue;
+            };
+            // every pair once, the root is its own mirror image
+            if *key >= center_key(self.dims - p) {
+                continue;
+            }
+            let (a, b) = (&self.cells.motion[id], &self.cells.motion[other]);
+            if !(a.stopped && b.stopped) {
+                continue;
+            }
+            pairs += 1;
+            let diff = (a.flip_t - b.flip_t).abs();
+            max_diff = max_diff.max(diff);
+            if a.expired != b.expired || diff > MIRROR_TOLERANCE {
+                differ += 1;
             }
         }
-        true
+        format!(
+            "symmetry check: {} pairs, {} differ by more than {:e} s, max flip time difference {:e} s",
+            pairs, differ, MIRROR_TOLERANCE, max_diff
+        )
+    }
+
+    /// the cells that can never flip are found before they run
+    fn checks_never(&self) -> bool {
+        // the energy only bounds the motion of the equations that keep it
+        self.config.skip_never && self.config.equations == Equations::Lagrange
+    }
+
+    /// what the energy check saved: a skipped cell can not flip, it would have run until max_step. counted in
+    /// ticks (steps of STEP_DELTA) as max_step is, adaptive integrators make steps of other lengths
+    pub fn never_report(&self) -> String {
+        if !self.checks_never() {
+            return "never flips: not checked, it needs --equations lagrange and --skip-never true".into();
+        }
+        let ticks = self.cells.motion.iter().map(|m| m.ticks()).sum::<usize>();
+        let saved = self.never * self.config.max_step;
+        let mut res = format!("never flips: {} of {} cells, {} ticks skipped", self.never, self.cells.len(), saved);
+        if ticks > 0 {
+            res += &format!(", {:.2}x less simulated time", (ticks + saved) as f64 / ticks as f64);
+        }
+        res
+    }
+
+    /// the energy error of the integrator: the largest and the mean drift (relative to the energy
+    /// scale) of the cells that ran
+    pub fn drift_report(&self) -> String {
+        let run = self.cells.motion.iter().filter(|m| m.stopped && !m.never).map(|m| m.drift.abs());
+        let (n, sum, max) = run.fold((0, 0.0, 0.0_f64), |(n, sum, max), d| (n + 1, sum + d, max.max(d)));
+        if n == 0 {
+            return "energy drift: no cell ran".into();
+        }
+        format!("energy drift: max {:.3e}, mean {:.3e} of {} cells ({:?})", max, sum / n as f64, n, self.config.integrator)
+    }
+
+    /// build what a checkpoint does not hold: the policy, and every stopped cell queued for drawing on
+    /// a new canvas, in the order they were made
+    pub fn restore(&mut self) {
+        self.policy = self.config.policy();
+        self.to_draw = (0..self.cells.len()).filter(|&id| self.cells.motion[id].stopped).collect();
+    }
+
+    pub fn can_remove(&self, id: usize) -> bool {
+        if id >= self.cells.len() || !self.cells.motion[id].stopped {
+            return false;
+        }
+        self.cells.childs[id].iter().all(|c| self.cells.motion[*c].stopped)
     }
 
     pub fn len(&self) -> usize {
         self.ps.len()
     }
 
-    pub fn find_all(&self, x: f64, y: f64) -> Option<Rc<RefCell<DoublePendulum>>> {
-        let mut res = Vec::new();
-        for (_, pref) in self.done.iter().chain(self.ps.iter()) {
-            let p = pref.borrow();
-            if p.point_inside(x, y) {
-                res.push(pref.clone())
-            }
-            // if p.borrow_mut().point_inside(x, y) {
-            //     return Some(p.clone());
-            // }
-        }
-        res.sort_by(|a, b| {
-            (a.borrow().scale, a.borrow().stopped)
-                .partial_cmp(&(b.borrow().scale, b.borrow().stopped))
-                .unwrap()
-        });
-        println!("found at {:?}", (x, y));
-        for r in &res {
-            let r = r.borrow();
-            println!(
-                "#{} <= {} {:?} {}       {}",
-                r.id,
-                r.parent_id,
-                r.rect(),
-                r.stopped,
-                r.steps,
-            );
-            // break;
+    /// number of stopped cells
+    pub fn done(&self) -> usize {
+        self.cells.len() - self.ps.len()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// a fresh pendulum with the same initial condition as `p`
+    pub fn replay(&self, p: &DoublePendulum) -> DoublePendulum {
+        DoublePendulum::new2(p.p, self.dims, p.scale, &self.config)
+    }
+
+    /// window size, pixels
+    pub fn dims(&self) -> DVec2 {
+        self.dims
+    }
+
+    /// id of the smallest cell at (x, y), down the childs from the root
+    pub fn find(&self, x: f64, y: f64) -> Option<usize> {
+        if self.cells.len() == 0 || !self.cells.point_inside(0, x, y) {
+            return None;
         }
-        match res.len() {
-            0 => None,
-            _ => Some(res[0].clone()),
+        let mut id = 0;
+        while let Some(&c) = self.cells.childs[id].iter().find(|&&c| self.cells.point_inside(c, x, y)) {
+            id = c;
         }
+        Some(id)
     }
 
-    fn update_neighbors(&mut self, parent_id: usize, childs: &mut Vec<DoublePendulum>) {
-        let mut nbs = Vec::new(); // ids of childs; all four new childs are neighbors to each other
-        let mut p = {
-            // parent must be stopped
-            self.done[&parent_id].borrow().clone()
-        };
-        for c in &mut *childs {
-            self.counter += 1;
-            c.id = self.counter;
-            c.parent_id = p.id;
-            nbs.push(c.id);
-            for nid in &p.neighbors {
-                if *nid == parent_id {
-                    continue;
-                }
-                // if any of ngs of p is adjacent to new pendulum - add it to its ngs
-                // and update p's ngs
-                let pn = self.done.get_mut(nid).or(self.ps.get_mut(nid));
-                if let Some(pn) = pn {
-                    let mut pn = pn.borrow_mut();
-                    if c.adjacent(&pn) {
-                        c.neighbors.push(pn.id);
-                        pn.neighbors.push(c.id);
+    /// the smallest cell at (x, y)
+    pub fn find_all(&self, x: f64, y: f64) -> Option<DoublePendulum> {
+        self.find(x, y).map(|id| self.cells.get(id))
+    }
+
+    /// whether the cell `id` has stopped and how many childs it has, the hover text of (x, y) changes with those of
+    /// `find(x, y)` only
+    pub fn hover_key(&self, id: usize) -> (bool, usize) {
+        (self.cells.motion[id].stopped, self.cells.childs[id].len())
+    }
+
+    /// store the childs of the split cells and link them with the neighbors of their parents, returns their ids
+    fn update_neighbors(&mut self, splits: Vec<(usize, Vec<DoublePendulum>)>) -> Vec<usize> {
+        // as in the baseline the cells made in this frame are not linked with each other, apart from the childs of
+        // one parent. so the links only depend on the cells from before and are found in parallel, in the same order
+        let first = self.cells.len();
+        let splits = splits
+            .into_iter()
+            .map(|(pid, childs)| (pid, childs.into_iter().map(|c| self.add(c, Some(pid))).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        let cells = &self.cells;
+        let links = splits
+            .par_iter()
+            .map(|(pid, nbs)| {
+                let mut links = Vec::new();
+                for &c in nbs {
+                    for &nid in &cells.neighbors[*pid] {
+                        // if any of ngs of p is adjacent to new pendulum - add it to its ngs
+                        if nid != *pid && nid < first && cells.adjacent(c, nid) {
+                            links.push((c, nid));
+                        }
                     }
                 }
+                links
+            })
+            .collect::<Vec<_>>();
+        let mut ids = Vec::new();
+        for ((_, nbs), links) in splits.into_iter().zip(links) {
+            for (c, nid) in links {
+                self.cells.neighbors[c].push(nid);
+                // a running neighbor does not get the new cell
+                if self.cells.motion[nid].stopped {
+                    self.cells.neighbors[nid].push(c);
+                }
             }
+            // all four new childs are neighbors to each other
+            for &c in &nbs {
+                self.cells.neighbors[c].extend(nbs.iter().copied());
+            }
+            ids.extend(nbs);
         }
-        for c in childs {
-            c.neighbors.extend(nbs.clone());
-        }
+        ids
     }
 
-    pub fn dive(&mut self, p: &mut DoublePendulum) -> Vec<(usize, Vec<DoublePendulum>)> {
-        /* if adjacent stopped pendulums has large diff with p - split p and adjacent into smaller pixels
-         */
-        assert!(p.stopped);
-        let nearest = &p.neighbors;
+    /// stopped neighbors of the stopped cell `id` that differ too much from it. only reads the cells,
+    /// so it runs for all the cells stopped in a frame at once
+    fn differs(cells: &Arena, policy: &dyn PendulumPolicy, dive_diff: f64, id: usize) -> Vec<usize> {
+        let p = &cells.motion[id];
+        cells.neighbors[id]
+            .iter()
+            .copied()
+            .filter(|&nid| {
+                let n = &cells.motion[nid];
+                if nid == id || !n.stopped {
+                    return false;
+                }
+                // do not split expired too small. a flipping cell can hide between two that can not flip at their
+                // centers, so those split down to the same size
+                let (p_gray, n_gray) = (p.expired || p.never, n.expired || n.never);
+                (p_gray || n_gray || policy.differ(p, n, dive_diff))
+                    && cells.adjacent(id, nid)
+                    && !(p_gray && n_gray && cells.width(nid) < 16.0)
+            })
+            .collect()
+    }
+
+    /// if adjacent stopped pendulums has large diff with `id` - split it and the adjacent into smaller pixels
+    pub fn dive(&mut self, id: usize, differs: Vec<usize>) -> Vec<(usize, Vec<DoublePendulum>)> {
+        assert!(self.cells.motion[id].stopped);
         let mut skip = HashSet::new();
-        skip.insert(p.id);
+        skip.insert(id);
 
         // if neighbor is different, but already in split list - split only current
         let mut add_current = false;
 
         let mut to_update = Vec::new();
-        for id in nearest {
-            if skip.contains(id) {
+        for nid in differs {
+            if skip.contains(&nid) {
                 continue;
             }
-            if let Some(nref) = self.done.get(id) {
-                let mut n = nref.borrow_mut();
-                let (asteps, bsteps) = (p.steps.min(n.steps), p.steps.max(n.steps));
-                let psteps = asteps as f64 / bsteps as f64;
-                if (psteps < self.config.dive_diff || p.expired || n.expired) && p.adjacent(&n) {
-                    if p.expired && n.expired && n.width()  < 16.0 {
-                        // do not split expired too small
-                        continue;
-                    }
-                    // println!("{} / {} = {:.3}", asteps, bsteps, psteps);
-                    if self.dive.contains(&n.id) {
-                        // if n is already in split list - split only current
-                        add_current = true;
-                        continue;
-                    }
-                    let mut childs = n.split(self.width, self.config.min_pixel, &self.config);
-                    to_update.push((n.id, childs));
-                    skip.insert(n.id);
-                    self.dive.insert(n.id);
-                }
+            // println!("{} / {} = {:.3}", at, bt, pt);
+            if self.dive.contains(&nid) {
+                // if n is already in split list - split only current
+                add_current = true;
+                continue;
             }
+            let childs = self.cells.split(nid, &self.config);
+            to_update.push((nid, childs));
+            skip.insert(nid);
+            self.dive.insert(nid);
         }
-        if !self.dive.contains(&p.id) && !(p.expired && p.width() < 16.0) && (to_update.len() > 0 || add_current) {
-            let mut childs = p.split(self.width, self.config.min_pixel, &self.config);
-            self.dive.insert(p.id);
-            to_update.push((p.id, childs));
+        let p = &self.cells.motion[id];
+        if !(self.dive.contains(&id) || (p.expired || p.never) && self.cells.width(id) < 16.0) && (!to_update.is_empty() || add_current) {
+            let childs = self.cells.split(id, &self.config);
+            self.dive.insert(id);
+            to_update.push((id, childs));
         }
         to_update
     }
 
-    pub fn dive_all(&mut self, ps: &mut Vec<Rc<RefCell<DoublePendulum>>>) -> HashMap<usize, Rc<RefCell<DoublePendulum>>> {
-        let mut next = HashMap::new();
+    /// split the cells around the stopped `ps`, returns the ids of the new cells
+    pub fn dive_all(&mut self, ps: &[usize]) -> Vec<usize> {
+        let (cells, policy, dive_diff) = (&self.cells, &*self.policy, self.config.dive_diff);
+        let differs = ps.par_iter().map(|&id| Self::differs(cells, policy, dive_diff, id)).collect::<Vec<_>>();
         let mut to_update = Vec::new();
-        for p in &mut *ps {
-            let mut p = p.borrow_mut();
-            to_update.extend(self.dive(&mut p));
-        }
-        for (pid, mut childs) in to_update {
-            self.update_neighbors(pid, &mut childs);
-            for c in childs {
-                next.insert(c.id, Rc::new(RefCell::new(c)));
-            }
+        for (&id, differs) in ps.iter().zip(differs) {
+            to_update.extend(self.dive(id, differs));
         }
-        next
-    }
-
-    fn color_step(&self) -> usize {
-        (self.config.color_step * STEP_DELTA) as usize
+        self.update_neighbors(to_update)
     }
 
-    pub fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+    pub fn update(&mut self) -> GameResult<()> {
         let t = Instant::now();
-        let (sender, receiver) = bounded::<DoublePendulum>(self.len());
-        let (res_sender, res_receiver) = bounded::<DoublePendulum>(self.len());
         let upd_steps = self.update_steps;
         let max_steps = self.config.max_step;
-        let workers = (1..12)
-            .map(|_| {
-                let receiver = receiver.clone();
-                let res_sender = res_sender.clone();
-                thread::spawn(move || {
-                    while let Ok(mut p) = receiver.recv() {
-                        p.update(upd_steps, max_steps).unwrap();
-                        res_sender.send(p).unwrap();
-                    }
-                })
-            })
-            .collect::<Vec<_>>();
-
-        for pref in &mut self.ps.values_mut() {
-            sender.send(pref.borrow().clone()).unwrap();
-        }
-        drop(sender);
-        for w in workers {
-            w.join().unwrap();
-        }
-        drop(res_sender);
-
-        let mut ps = HashMap::new();
-        while let Ok(p) = res_receiver.recv() {
-            ps.insert(p.id, Rc::new(RefCell::new(p)));
-        }
+        let integrator = self.config.integrator.build(self.config.atol, self.config.rtol);
+        let policy = &*self.policy;
+        let ps = self.ps.iter().copied().filter(|id| !self.twins.contains_key(id)).collect::<Vec<_>>();
+        // runs on the global pool, see --threads
+        self.cells
+            .motions_mut(&ps)
+            .into_par_iter()
+            .for_each(|m| m.update(upd_steps, max_steps, &*integrator, policy));
+        self.copy_twins();
         let t_phys = t.elapsed();
 
         let t = Instant::now();
         self.iter += 1;
-        let mut next = HashMap::new();
-        let mut stopped = Vec::new();
-        let color_step = self.color_step();
-        for pref in &mut ps.values_mut() {
-            let mut p = pref.borrow_mut();
-            if p.stopped {
-                stopped.push(pref.clone());
-
-                self.done.insert(p.id, pref.clone());
-
-                p.update_color(color_step, self.config.color_mod);
-                self.to_draw.push_back(pref.clone());
-
-                self.avg.add(p.steps as u32);
-            }
-            else {
-                next.insert(p.id, pref.clone());
-            }
+        let (stopped, running): (Vec<usize>, Vec<usize>) =
+            self.ps.iter().partition(|&&id| self.cells.motion[id].stopped);
+        self.ps = running;
+        for &id in &stopped {
+            let m = &self.cells.motion[id];
+            self.cells.color[id] = self.policy.color(m);
+            self.avg.add(m.steps as u32);
+            self.to_draw.push_back(id);
         }
         println!("phys {:?} {:?} stopped={}", t_phys, t.elapsed(), stopped.len());
-        let t = Instant::now();
-        if (next.len() == 0 && self.done.len() == 1) || (next.len() == 1 && self.done.len() == 0 && self.iter >= 1) {
-            let pref = self.done.values().next().unwrap_or(next.values().next().unwrap()).clone();
-            let (mut childs, p_id) = {
-                // special case: only for the first pendulum
-                let mut p = pref.borrow_mut();
-                if !p.stopped {
-                    p.stopped = true;
-                    p.update_color(color_step, self.update_steps);
-                }
-                let mut childs = p.split(self.width, self.config.min_pixel, &self.config);
-                (childs, p.id)
-            };
-            self.done.insert(p_id, pref.clone());
-            self.update_neighbors(p_id, &mut childs);
-            for n in childs {
-                next.insert(n.id, Rc::new(RefCell::new(n)));
+        if (self.ps.is_empty() && self.done() == 1) || (self.ps.len() == 1 && self.done() == 0 && self.iter >= 1) {
+            // special case: only for the first pendulum
+            let id = 0;
+            let m = &mut self.cells.motion[id];
+            if !m.stopped {
+                (m.stopped, m.flip_t) = (true, m.t);
+                m.drift = m.energy_drift();
+                // colored with the steps of this frame as color_mod, and once more with the config one in the next
+                // frame, where it is still in ps
+                let policy = Config { color_mod: self.update_steps, ..self.config.clone() }.policy();
+                self.cells.color[id] = policy.color(m);
+                self.to_draw.push_back(id);
             }
+            let childs = self.cells.split(id, &self.config);
+            let ids = self.update_neighbors(vec![(id, childs)]);
+            self.ps.extend(ids);
         }
 
         let t = Instant::now();
@@ -626,66 +891,100 @@ impl PendulumFamily {
 
         self.update_steps = 10 + ((self.iter as f64 / self.config.speed_a).exp() / self.config.speed_b).exp() as usize;
 
-        let dive = self.dive_all(&mut stopped);
+        let dive = self.dive_all(&stopped);
         println!("dive: {:?}", t.elapsed());
         let new_cnt = dive.len();
-        next.extend(dive);
+        self.ps.extend(dive);
 
-        if self.ps.len() > 0 {
+        if !self.ps.is_empty() {
             println!(
                 "[{}] {:?} +{}, -{}: active: {}, done: {}, avg: {}, steps: {}",
                 self.iter,
                 self.t.elapsed(),
                 new_cnt,
                 stopped.len(),
-                next.len(),
-                self.done.len(),
+                self.ps.len(),
+                self.done(),
                 self.avg.get(),
                 self.update_steps
             );
         }
-        self.ps = next;
+        else if !stopped.is_empty() {
+            println!("{}", self.never_report());
+            println!("{}", self.drift_report());
+            if self.symmetric {
+                println!("{}", self.symmetry_report());
+            }
+        }
         self.t = Instant::now();
+        Ok(())
+    }
 
-        // if self.ps.len() == 0 {
-        //     for p in self.done.values() {
-        //         let p = p.borrow();
-        //         if p.width() > self.config.min_pixel / 2.0 || p.expired {
-        //             continue;
-        //         }
-
-        //         for n in p.neighbors.iter().map(|n| &self.done[n]) {
-        //             let n = n.borrow();
-        //             if n.width() > self.config.min_pixel / 2.0 || p.expired {
-        //                 continue
-        //             }
-        //             let (asteps, bsteps) = (p.steps.min(n.steps), p.steps.max(n.steps));
-        //             let psteps = asteps as f64 / bsteps as f64;
-        //             if 0.7 < psteps && psteps < 0.99 {
-        //                 println!("({}, {}),", asteps, bsteps);
-        //             }
-        //         }
-        //     }
-        // }
+    /// headless counterpart of `draw`: paint the newly stopped pendulums into `img`
+    pub fn rasterize(&mut self, img: &mut Raster) {
+        while let Some(id) = self.to_draw.pop_front() {
+            img.fill_rect(self.cells.rect(id), self.cells.color[id]);
+        }
+    }
+
+    /// flip times of the cells on a `dims` sized grid, see `FlipField`, or with the lyapunov policy the exponents,
+    /// which run for the same time. None until every cell has stopped, so NaN is always an expired cell
+    pub fn flip_field(&self) -> Option<FlipField> {
+        if !self.ps.is_empty() {
+            return None;
+        }
+        let mut field = FlipField::new(self.dims.x as u32, self.dims.y as u32);
+        // in the order they were made, the childs over their parents
+        for (id, m) in self.cells.motion.iter().enumerate().filter(|(_, m)| m.stopped) {
+            let v = match (m.never, m.expired) {
+                (true, _) => field::NEVER,
+                (false, true) => f32::NAN,
+                (false, false) if self.config.policy == PolicyKind::Lyapunov => lyapunov::exponent(m) as f32,
+                (false, false) => m.flip_t as f32,
+            };
+            field.fill_rect(self.cells.rect(id), v);
+        }
+        Some(field)
+    }
+
+    /// the cell `id`, and the arms over it while it runs
+    fn draw_cell(&self, ctx: &mut Context, id: usize) -> GameResult<()> {
+        let (m, p) = (&self.cells.motion[id], self.cells.p[id]);
+        let mb = &mut graphics::MeshBuilder::new();
+        mb.rectangle(DrawMode::fill(), self.cells.rect(id), self.cells.color[id])?;
+        if !m.stopped {
+            let pcolor = self.policy.pcolor(m);
+            // both arms together reach the nearest edge of the cell
+            let (r, l1, l2) = (self.cells.width(id), m.params.l1, m.params.l2);
+            let (a1, a2) = (r * l1 / (l1 + l2), r * l2 / (l1 + l2));
+            let (ts1, tc1) = m.state.theta1.sin_cos();
+            let p1 = p + dvec2(ts1 * a1, tc1 * a1);
+            let (ts2, tc2) = m.state.theta2.sin_cos();
+            let p2 = p1 + dvec2(ts2 * a2, tc2 * a2);
+            mb.line(&[p.as_vec2(), p1.as_vec2(), p2.as_vec2()], 3.0, pcolor)?;
+        }
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, (vec2(0.0, 0.0), Color::WHITE))?;
         Ok(())
     }
 
     pub fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         let t = Instant::now();
-        graphics::set_canvas(ctx, Some(&self.canvas));
+        if self.canvas.is_none() {
+            self.canvas = Some(Canvas::with_window_size(ctx)?);
+        }
+        let canvas = self.canvas.as_ref().unwrap();
+        graphics::set_canvas(ctx, Some(canvas));
         let draw_len = self.to_draw.len();
-        while let Some(p) = self.to_draw.pop_front() {
-            p.borrow_mut().draw(ctx)?;
+        while let Some(id) = self.to_draw.pop_front() {
+            self.draw_cell(ctx, id)?;
         }
         graphics::set_canvas(ctx, None);
-        graphics::draw(ctx, &self.canvas, (vec2(0.0, 0.0), (1.0, 1.0, 1.0, 1.0).into()))?;
+        graphics::draw(ctx, canvas, (vec2(0.0, 0.0), (1.0, 1.0, 1.0, 1.0).into()))?;
         let mut p_drawn = 0;
-        for p in self.ps.values() {
-            let mut p = p.borrow_mut();
-            if p.width() > 2.0 * self.config.min_pixel || true {
-                p.draw(ctx)?;
-                p_drawn += 1;
-            }
+        for &id in &self.ps {
+            self.draw_cell(ctx, id)?;
+            p_drawn += 1;
         }
         if draw_len > 0 {
             println!("draw: {} / {} - {:?}", draw_len, p_drawn, t.elapsed());
@@ -694,21 +993,52 @@ impl PendulumFamily {
     }
 }
 
+/// the small render of the tests: 3000 steps, arms of 64 and cells down to 16 pixels
+#[cfg(test)]
+pub(crate) fn small_config() -> Config {
+    Config { max_step: 3000, min_pixel: 16.0, l1: Some(64.0), l2: Some(64.0), ..Default::default() }
+}
+
+/// update `family` until every cell has stopped
+#[cfg(test)]
+pub(crate) fn run_all(family: &mut PendulumFamily) {
+    while {
+        family.update().unwrap();
+        family.len() > 0
+    } {}
+}
+
+/// the whole render of `config` on 128x128 pixels
+#[cfg(test)]
+pub(crate) fn run_small(config: Config) -> PendulumFamily {
+    let mut family = PendulumFamily::new(config, dvec2(128.0, 128.0));
+    family.init();
+    run_all(&mut family);
+    family
+}
+
+#[cfg(test)]
 mod test {
+    use std::f64::consts::TAU;
+
     use glam::*;
     use tap::Tap;
 
+    use crate::arena::Arena;
+    use crate::config::preset;
     use crate::pendulum::PendulumFamily;
 
-    use super::{Config, DoublePendulum, L1};
+    use super::{flip_fraction, run_all, run_small, small_config, Config, DoublePendulum, FlipInterp, Symmetry,
+                MIRROR_TOLERANCE, STEP_DELTA};
+    use crate::lyapunov;
+    use crate::physics::{Equations, IntegratorKind, Quantity};
+    use crate::policy::PolicyKind;
+    use crate::region::Region;
 
     #[test]
     fn test_adjacent() {
         let config = Config {
-            xmin:       0.0,
-            xmax:       3.0,
-            ymin:       0.0,
-            ymax:       3.0,
+            region:     Region::new(0.0, 3.0, 0.0, 3.0),
             color_step: 100.0,
             dive_diff:  0.92,
             max_step:   10_000,
@@ -716,19 +1046,197 @@ mod test {
             color_mod:  6000,
             speed_a:    550.0,
             speed_b:    20.0,
+            ..Default::default()
+        };
+        const WIDTH: f64 = 2048.0;
+        let dims = dvec2(WIDTH, WIDTH);
+        let mut cells = Arena::new(dims);
+        let p1 = cells.insert(DoublePendulum::new2(dvec2(WIDTH / 4.0, WIDTH / 4.0), dims, 0.5, &config), None);
+        let p2 = DoublePendulum::new2(dvec2(WIDTH / 4.0, WIDTH / 4.0 * 3.0), dims, 0.5, &config);
+        let p3 = cells.insert(p2.clone().tap_mut(|p| p.p.y += 1.0), None);
+        let p2 = cells.insert(p2, None);
+        assert!(cells.adjacent(p1, p2));
+        assert!(cells.adjacent(p2, p1));
+        assert!(!cells.adjacent(p1, p3));
+        assert!(!cells.adjacent(p3, p1));
+        let p4 = cells.insert(DoublePendulum::new2(dvec2(WIDTH / 4.0 * 3.0, WIDTH / 4.0), dims, 0.5, &config), None);
+        assert!(cells.adjacent(p1, p4));
+        assert!(cells.adjacent(p4, p1));
+    }
+
+    #[test]
+    fn test_arm_lengths() {
+        // a quarter of the width unless set, 512 at the classic 2048
+        let config = Config::default();
+        let p = DoublePendulum::new2(dvec2(512.0, 512.0), dvec2(2048.0, 1024.0), 1.0, &config);
+        assert_eq!((p.params().l1, p.params().l2), (512.0, 512.0));
+        assert_eq!(config.start(0.5, 0.5, 400.0).0.l1, 100.0);
+        let set = Config { l1: Some(3.0), ..config };
+        assert_eq!(set.arms(400.0), (3.0, 100.0));
+    }
+
+    #[test]
+    fn test_split_wide() {
+        let config = Config::default();
+        let dims = dvec2(1600.0, 400.0);
+        let mut cells = Arena::new(dims);
+        let p = cells.insert(DoublePendulum::new2(dims / 2.0, dims, 1.0, &config), None);
+        cells.motion[p].stopped = true;
+        let childs = cells.split(p, &Config { min_pixel: 4.0, ..config.clone() });
+        assert_eq!(childs.len(), 4);
+        for c in &childs {
+            assert!(cells.point_inside(p, c.p.x, c.p.y));
+        }
+        // the left edge is xmin, the top edge is ymin
+        let start = |x: f64, y: f64| config.start(x / dims.x, y / dims.y, dims.x).1;
+        assert_eq!(childs[0].state().theta1, start(400.0, 100.0).theta1);
+        assert_eq!(childs[0].state().theta2, start(400.0, 100.0).theta2);
+        let far = childs[3].clone().tap_mut(|c| c.p.x += 800.0);
+        let ids = childs.into_iter().map(|c| cells.insert(c, Some(p))).collect::<Vec<_>>();
+        for &c in &ids {
+            assert_eq!((cells.rect(c).w, cells.rect(c).h), (800.0, 200.0));
+        }
+        assert!(cells.adjacent(ids[0], ids[1]));
+        assert!(cells.adjacent(ids[0], ids[2]));
+        assert!(cells.adjacent(ids[1], ids[3]));
+        let far = cells.insert(far, None);
+        assert!(!cells.adjacent(ids[0], far));
+        assert!(cells.adjacent(ids[3], far));
+    }
+
+    #[test]
+    fn test_drift() {
+        let run = |integrator| run_small(Config { integrator, equations: Equations::Lagrange, ..small_config() });
+        let max = |f: &PendulumFamily| f.cells.motion.iter().map(|m| m.drift.abs()).fold(0.0, f64::max);
+        let (euler, rk4) = (run(IntegratorKind::Euler), run(IntegratorKind::Rk4));
+        // the first order integrator loses percents, rk4 nothing visible
+        assert!(max(&euler) > 1e-3 && max(&rk4) < 1e-6, "{} {}", max(&euler), max(&rk4));
+        for m in euler.cells.motion.iter().filter(|m| !m.never) {
+            assert_eq!(m.drift, m.energy_drift());
+        }
+        assert!(euler.drift_report().starts_with("energy drift: max "), "{}", euler.drift_report());
+        assert!(euler.never_report().contains(" cells, "), "{}", euler.never_report());
+    }
+
+    #[test]
+    fn test_replay() {
+        let config = Config { max_step: 20_000, ..Default::default() };
+        let dims = dvec2(256.0, 256.0);
+        let family = PendulumFamily::new(config.clone(), dims);
+        let integrator = config.integrator.build(config.atol, config.rtol);
+        let run = |mut p: DoublePendulum, chunk: usize| {
+            while !p.motion.stopped {
+                p.update(chunk, config.max_step, &*integrator, &*config.policy()).unwrap();
+            }
+            p
         };
-        let WIDTH = 2048.0;
-        let p1 = DoublePendulum::new2(dvec2(WIDTH / 4.0, WIDTH / 4.0), WIDTH, 0.5, &config).tap_mut(|p| p.id = 1);
-        let p2 = DoublePendulum::new2(dvec2(WIDTH / 4.0, WIDTH / 4.0 * 3.0), WIDTH, 0.5, &config).tap_mut(|p| p.id = 2);
-        assert!(p1.adjacent(&p2));
-        assert!(p2.adjacent(&p1));
-        let mut p3 = p2.clone();
-        p3.p.y += 1.0;
-        assert!(!p1.adjacent(&p3));
-        assert!(!p3.adjacent(&p1));
-        let p4 = DoublePendulum::new2(dvec2(WIDTH / 4.0 * 3.0, WIDTH / 4.0), WIDTH, 0.5, &config).tap_mut(|p| p.id = 3);
-        assert!(p1.adjacent(&p4));
-        assert!(p4.adjacent(&p1));
+        let cell = run(DoublePendulum::new2(dvec2(96.0, 160.0), dims, 0.125, &config), 100);
+        assert!(!cell.expired());
+        let replay = run(family.replay(&cell), 7);
+        assert_eq!(replay.motion.flip_t, cell.motion.flip_t);
+        assert_eq!(replay.motion.steps, cell.motion.steps);
+    }
+
+    #[test]
+    fn test_mirror() {
+        let config = Config { region: Region::new(-3.0, 3.0, -3.0, 3.0), ..small_config() };
+        assert!(config.mirrored());
+        assert!(Config { region: Region::new(0.0, TAU, -1.0, 1.0), ..config.clone() }.mirrored());
+        assert!(!Config::default().mirrored());
+        assert!(!Config { dt1: 0.5, ..config.clone() }.mirrored());
+        assert!(!Config { y_axis: Quantity::L2, ..config.clone() }.mirrored());
+
+        let run = |symmetry| run_small(Config { symmetry, ..config.clone() });
+        let (off, mirror) = (run(Symmetry::Off), run(Symmetry::Mirror));
+        assert_eq!(off.cells.len(), mirror.cells.len());
+        // half of the cells that run, the root runs alone
+        assert_eq!(mirror.mirrored, (off.cells.len() - off.never) / 2);
+        for id in 0..off.cells.len() {
+            let (a, b) = (&off.cells.motion[id], &mirror.cells.motion[id]);
+            assert_eq!(off.cells.p[id], mirror.cells.p[id]);
+            assert_eq!((a.expired, a.never), (b.expired, b.never));
+            assert!((a.flip_t - b.flip_t).abs() <= MIRROR_TOLERANCE, "{} {}", a.flip_t, b.flip_t);
+        }
+        assert!(run(Symmetry::Check).symmetry_report().contains(" 0 differ"));
+
+        // theta2 past the tops: cells that start over one of them flip on the way back
+        let past = Config { region: Region::new(-3.0, 3.0, -4.0, 4.0), symmetry: Symmetry::Check, ..config.clone() };
+        for integrator in [IntegratorKind::Euler, IntegratorKind::Rk4] {
+            let family = run_small(Config { integrator, ..past.clone() });
+            assert!(family.symmetry_report().contains(" 0 differ"), "{}", family.symmetry_report());
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let family = run_small(small_config());
+        // the walk down the childs finds the smallest of all the cells there
+        for (x, y) in (0..64).map(|i| (i as f64 * 2.0 + 0.5, 127.5 - i as f64 * 1.5)) {
+            let scan = (0..family.cells.len())
+                .filter(|&id| family.cells.point_inside(id, x, y))
+                .min_by(|&a, &b| family.cells.scale[a].partial_cmp(&family.cells.scale[b]).unwrap());
+            assert_eq!(family.find(x, y), scan, "{} {}", x, y);
+        }
+        assert_eq!(family.find(-1.0, 5.0), None);
+    }
+
+    #[test]
+    fn test_baseline() {
+        // FNV-1a of the center, scale, steps and expiry of every cell ordered by center and scale, computed by the
+        // code of the baseline commit for the same render
+        const BASELINE: (usize, u64) = (3285, 0x1a6e3a540122965a);
+        let family = run_small(Config { max_step: 5000, min_pixel: 2.0, ..preset("baseline").unwrap() });
+        let cells = &family.cells;
+        let mut ids = (0..cells.len()).collect::<Vec<_>>();
+        let key = |id: usize| (cells.p[id].x, cells.p[id].y, cells.scale[id]);
+        ids.sort_by(|&a, &b| key(a).partial_cmp(&key(b)).unwrap());
+        let mut hash = 0xcbf29ce484222325u64;
+        for id in ids {
+            let m = &cells.motion[id];
+            // the baseline had the steps only, the flip time must be the same
+            assert_eq!((m.flip_t / STEP_DELTA).round() as usize, m.steps);
+            let (p, scale) = (cells.p[id], cells.scale[id]);
+            for k in [p.x.to_bits(), p.y.to_bits(), scale.to_bits(), m.steps as u64, m.expired as u64] {
+                hash = (hash ^ k).wrapping_mul(0x100000001b3);
+            }
+        }
+        assert_eq!((cells.len(), hash), BASELINE);
+        assert!(family.never_report().contains("not checked"), "{}", family.never_report());
+    }
+
+    #[test]
+    fn test_flip_field() {
+        let mut family = PendulumFamily::new(small_config(), dvec2(128.0, 128.0));
+        family.init();
+        family.update().unwrap();
+        assert!(family.len() > 0 && family.flip_field().is_none());
+        run_all(&mut family);
+        // NaN is an expired cell and nothing else
+        let field = family.flip_field().unwrap();
+        for (x, y) in (0..128).flat_map(|y| (0..128).map(move |x| (x, y))) {
+            let id = family.find(x as f64 + 0.5, y as f64 + 0.5).unwrap();
+            assert_eq!(field.get(x, y).is_nan(), family.cells.motion[id].expired, "{} {}", x, y);
+        }
+
+        // every lyapunov cell runs for lyap_time, the field has the exponents
+        let family = run_small(Config { policy: PolicyKind::Lyapunov, lyap_time: 5.0, ..small_config() });
+        let field = family.flip_field().unwrap();
+        let m = &family.cells.motion[family.find(40.5, 90.5).unwrap()];
+        assert!(m.flip_t >= 5.0 && field.get(40, 90) == lyapunov::exponent(m) as f32);
+    }
+
+    #[test]
+    fn test_flip_fraction() {
+        let pi = std::f64::consts::PI;
+        assert_eq!(flip_fraction(FlipInterp::None, pi, (3.0, 1.0), (3.5, 1.0), 0.5), 1.0);
+        let s = flip_fraction(FlipInterp::Linear, pi, (3.0, 1.0), (3.5, 1.0), 0.5);
+        assert!((s - (pi - 3.0) / 0.5).abs() < 1e-12, "{}", s);
+        // y = 3 + t^2 over a step of 1s: hermite is exact for cubics
+        let s = flip_fraction(FlipInterp::Hermite, pi, (3.0, 0.0), (4.0, 2.0), 1.0);
+        assert!((s - (pi - 3.0).sqrt()).abs() < 1e-12, "{}", s);
+        // going down through -PI
+        let s = flip_fraction(FlipInterp::Hermite, -pi, (-3.0, -1.0), (-3.5, -1.0), 0.5);
+        assert!((s - (pi - 3.0) / 0.5).abs() < 1e-12, "{}", s);
     }
 }
 
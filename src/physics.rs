@@ -0,0 +1,521 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+pub const G: f64 = 9.81;
+
+/// phase-space state of a double pendulum: angles and angular velocities
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub theta1: f64,
+    pub theta2: f64,
+    pub dt1:    f64,
+    pub dt2:    f64,
+}
+
+impl State {
+    fn to_array(self) -> [f64; 4] {
+        [self.theta1, self.theta2, self.dt1, self.dt2]
+    }
+}
+
+impl From<[f64; 4]> for State {
+    fn from(y: [f64; 4]) -> Self {
+        State { theta1: y[0], theta2: y[1], dt1: y[2], dt2: y[3] }
+    }
+}
+
+/// arm lengths, bob masses and gravity. only the ratio of the masses matters for the motion
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Params {
+    pub l1:        f64,
+    pub l2:        f64,
+    pub m1:        f64,
+    pub m2:        f64,
+    pub g:         f64,
+    pub equations: Equations,
+}
+
+/// the equations of motion `accel` solves
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Equations {
+    /// the ones the pictures were first made with. the dt1^2 term of d2t1 has the wrong sign, so they
+    /// do not keep the energy and have no hamiltonian
+    #[default]
+    Baseline,
+    /// the euler-lagrange equations of the double pendulum, they keep the energy
+    Lagrange,
+}
+
+impl Equations {
+    /// sign of the dt1^2 l1 cos(theta1 - theta2) term of d2t1. -1 flips the bits of that term only, so
+    /// the baseline is computed bit for bit as before
+    pub fn sign(self) -> f64 {
+        match self {
+            Equations::Baseline => -1.0,
+            Equations::Lagrange => 1.0,
+        }
+    }
+}
+
+/// a physical quantity that can be put on an axis of the picture
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Quantity {
+    /// initial angle of the first arm
+    Theta1,
+    /// initial angle of the second arm
+    Theta2,
+    /// initial angular velocity of the first arm
+    Dt1,
+    /// initial angular velocity of the second arm
+    Dt2,
+    /// length of the first arm
+    L1,
+    /// length of the second arm
+    L2,
+    /// mass of the first bob
+    M1,
+    /// mass of the second bob
+    M2,
+    /// gravity
+    G,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the same name as on the command line
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+impl Quantity {
+    pub fn set(self, p: &mut Params, s: &mut State, v: f64) {
+        match self {
+            Quantity::Theta1 => s.theta1 = v,
+            Quantity::Theta2 => s.theta2 = v,
+            Quantity::Dt1 => s.dt1 = v,
+            Quantity::Dt2 => s.dt2 = v,
+            Quantity::L1 => p.l1 = v,
+            Quantity::L2 => p.l2 = v,
+            Quantity::M1 => p.m1 = v,
+            Quantity::M2 => p.m2 = v,
+            Quantity::G => p.g = v,
+        }
+    }
+
+    /// lengths and masses make no sense at or below zero
+    pub fn positive(self) -> bool {
+        matches!(self, Quantity::L1 | Quantity::L2 | Quantity::M1 | Quantity::M2)
+    }
+}
+
+/// angular accelerations (d2t1, d2t2) of `p.equations`
+#[inline(always)]
+pub fn accel(p: &Params, s: &State) -> (f64, f64) {
+    let (m1, m2, sign) = (p.m1, p.m2, p.equations.sign());
+    let a = 2.0 * m1 + m2 - m2 * (2.0 * s.theta1 - 2.0 * s.theta2).cos();
+
+    let d2t1 = (-p.g * (2.0 * m1 + m2) * s.theta1.sin()
+        - m2 * p.g * (s.theta1 - 2.0 * s.theta2).sin()
+        - 2.0
+            * (s.theta1 - s.theta2).sin()
+            * m2
+            * (s.dt2 * s.dt2 * p.l2 + sign * s.dt1 * s.dt1 * p.l1 * (s.theta1 - s.theta2).cos()))
+        / (p.l1 * a);
+
+    let d2t2 = (2.0
+        * (s.theta1 - s.theta2).sin()
+        * (s.dt1 * s.dt1 * p.l1 * (m1 + m2)
+            + p.g * (m1 + m2) * s.theta1.cos()
+            + s.dt2 * s.dt2 * p.l2 * m2 * (s.theta1 - s.theta2).cos()))
+        / (p.l2 * a);
+
+    (d2t1, d2t2)
+}
+
+/// total mechanical energy, the zero of the potential is at the fixed point
+pub fn energy(p: &Params, s: &State) -> f64 {
+    let (m1, m2) = (p.m1, p.m2);
+    let kin = 0.5 * (m1 + m2) * p.l1 * p.l1 * s.dt1 * s.dt1
+        + 0.5 * m2 * p.l2 * p.l2 * s.dt2 * s.dt2
+        + m2 * p.l1 * p.l2 * s.dt1 * s.dt2 * (s.theta1 - s.theta2).cos();
+    let pot = -(m1 + m2) * p.g * p.l1 * s.theta1.cos() - m2 * p.g * p.l2 * s.theta2.cos();
+    kin + pot
+}
+
+/// depth of the potential, (m1 + m2) g l1 + m2 g l2. the scale the energy errors are measured in,
+/// the energy itself can be zero
+pub fn energy_scale(p: &Params) -> f64 {
+    ((p.m1 + p.m2) * p.l1 + p.m2 * p.l2) * p.g.abs()
+}
+
+/// the least energy with the second arm straight up: the first arm hangs down (or up, if g < 0).
+/// below it the second arm can never get over the top
+pub fn flip_energy(p: &Params) -> f64 {
+    p.m2 * p.g * p.l2 - (p.m1 + p.m2) * p.g.abs() * p.l1
+}
+
+/// the least energy with the first arm straight up: the second arm hangs down (or up, if g < 0).
+/// below it the first arm can never get over the top
+pub fn flip1_energy(p: &Params) -> f64 {
+    (p.m1 + p.m2) * p.g * p.l1 - p.m2 * p.g.abs() * p.l2
+}
+
+/// canonical momenta conjugate to theta1, theta2
+fn momenta(p: &Params, s: &State) -> (f64, f64) {
+    let (m1, m2) = (p.m1, p.m2);
+    let c = (s.theta1 - s.theta2).cos();
+    let p1 = (m1 + m2) * p.l1 * p.l1 * s.dt1 + m2 * p.l1 * p.l2 * c * s.dt2;
+    let p2 = m2 * p.l2 * p.l2 * s.dt2 + m2 * p.l1 * p.l2 * c * s.dt1;
+    (p1, p2)
+}
+
+/// angular velocities from the canonical momenta
+fn velocities(p: &Params, theta1: f64, theta2: f64, p1: f64, p2: f64) -> (f64, f64) {
+    let (m1, m2) = (p.m1, p.m2);
+    let (sn, c) = (theta1 - theta2).sin_cos();
+    let d = m1 + m2 * sn * sn;
+    let dt1 = (p.l2 * p1 - p.l1 * c * p2) / (p.l1 * p.l1 * p.l2 * d);
+    let dt2 = ((m1 + m2) * p.l1 * p2 - m2 * p.l2 * c * p1) / (m2 * p.l1 * p.l2 * p.l2 * d);
+    (dt1, dt2)
+}
+
+/// hamilton's equations: (dtheta1, dtheta2, dp1, dp2) at (theta, p)
+fn hamilton(p: &Params, y: [f64; 4]) -> [f64; 4] {
+    let (m1, m2) = (p.m1, p.m2);
+    let [theta1, theta2, p1, p2] = y;
+    let (dt1, dt2) = velocities(p, theta1, theta2, p1, p2);
+    let k = m2 * p.l1 * p.l2 * dt1 * dt2 * (theta1 - theta2).sin();
+    [dt1, dt2, -k - (m1 + m2) * p.g * p.l1 * theta1.sin(), k - m2 * p.g * p.l2 * theta2.sin()]
+}
+
+/// (dtheta1, dtheta2, d2theta1, d2theta2) at y = (theta1, theta2, dt1, dt2)
+fn deriv(p: &Params, y: &[f64; 4]) -> [f64; 4] {
+    let (a1, a2) = accel(p, &State::from(*y));
+    [y[2], y[3], a1, a2]
+}
+
+/// y + h * sum(c * k)
+fn combine(y: &[f64; 4], h: f64, terms: &[(f64, &[f64; 4])]) -> [f64; 4] {
+    std::array::from_fn(|i| y[i] + h * terms.iter().map(|(c, k)| c * k[i]).sum::<f64>())
+}
+
+pub trait Integrator {
+    /// advance `s` by about `*h` seconds and return the time actually advanced.
+    /// adaptive integrators set `*h` to the size proposed for the next step
+    fn step(&self, p: &Params, s: &mut State, h: &mut f64) -> f64;
+}
+
+/// semi-implicit euler, first order. update the velocities, then the angles with the new velocities
+pub struct SemiImplicitEuler;
+
+impl Integrator for SemiImplicitEuler {
+    #[inline(always)]
+    fn step(&self, p: &Params, s: &mut State, h: &mut f64) -> f64 {
+        let h = *h;
+        let (d2t1, d2t2) = accel(p, s);
+
+        s.dt1 += d2t1 * h;
+        s.dt2 += d2t2 * h;
+
+        s.theta1 += s.dt1 * h;
+        s.theta2 += s.dt2 * h;
+
+        h
+    }
+}
+
+/// classic 4th order runge-kutta
+pub struct Rk4;
+
+impl Integrator for Rk4 {
+    fn step(&self, p: &Params, s: &mut State, h: &mut f64) -> f64 {
+        let h = *h;
+        let y = s.to_array();
+        let k1 = deriv(p, &y);
+        let k2 = deriv(p, &combine(&y, h / 2.0, &[(1.0, &k1)]));
+        let k3 = deriv(p, &combine(&y, h / 2.0, &[(1.0, &k2)]));
+        let k4 = deriv(p, &combine(&y, h, &[(1.0, &k3)]));
+        *s = combine(&y, h / 6.0, &[(1.0, &k1), (2.0, &k2), (2.0, &k3), (1.0, &k4)]).into();
+        h
+    }
+}
+
+/// velocity verlet, second order. the accelerations depend on the velocities, so the closing half-kick
+/// uses the mid-step velocities, and that makes it neither symplectic nor time-reversible here
+pub struct VelocityVerlet;
+
+impl Integrator for VelocityVerlet {
+    fn step(&self, p: &Params, s: &mut State, h: &mut f64) -> f64 {
+        let h = *h;
+        let (d2t1, d2t2) = accel(p, s);
+        s.dt1 += d2t1 * h / 2.0;
+        s.dt2 += d2t2 * h / 2.0;
+        s.theta1 += s.dt1 * h;
+        s.theta2 += s.dt2 * h;
+        let (a1, a2) = accel(p, s);
+        s.dt1 += a1 * h / 2.0;
+        s.dt2 += a2 * h / 2.0;
+        h
+    }
+}
+
+/// implicit midpoint rule on the canonical coordinates (theta, p): symplectic, second order. the
+/// implicit equation is solved by fixed-point iteration. it solves hamilton's equations, which are
+/// the `Equations::Lagrange` ones
+pub struct ImplicitMidpoint;
+
+impl ImplicitMidpoint {
+    const MAX_ITER: usize = 20;
+    const TOL: f64 = 1e-13;
+}
+
+impl Integrator for ImplicitMidpoint {
+    fn step(&self, p: &Params, s: &mut State, h: &mut f64) -> f64 {
+        let h = *h;
+        let (p1, p2) = momenta(p, s);
+        let y0 = [s.theta1, s.theta2, p1, p2];
+        // explicit euler as the first guess
+        let f0 = hamilton(p, y0);
+        let mut y: [f64; 4] = std::array::from_fn(|i| y0[i] + h * f0[i]);
+        for _ in 0..Self::MAX_ITER {
+            let f = hamilton(p, std::array::from_fn(|i| (y0[i] + y[i]) / 2.0));
+            let mut err: f64 = 0.0;
+            for i in 0..4 {
+                let next = y0[i] + h * f[i];
+                err = err.max((next - y[i]).abs() / (1.0 + next.abs()));
+                y[i] = next;
+            }
+            if err < Self::TOL {
+                break;
+            }
+        }
+        let (dt1, dt2) = velocities(p, y[0], y[1], y[2], y[3]);
+        *s = State { theta1: y[0], theta2: y[1], dt1, dt2 };
+        h
+    }
+}
+
+/// dormand-prince 5(4) with error control: a step is accepted when the local error
+/// estimate is below atol + rtol * |y| for every component
+pub struct DormandPrince {
+    pub atol: f64,
+    pub rtol: f64,
+}
+
+impl DormandPrince {
+    const MIN_H: f64 = 1e-9;
+    const MAX_H: f64 = 0.1;
+    const SAFETY: f64 = 0.9;
+    const MIN_SCALE: f64 = 0.2;
+    const MAX_SCALE: f64 = 5.0;
+
+    const A2: [f64; 1] = [1.0 / 5.0];
+    const A3: [f64; 2] = [3.0 / 40.0, 9.0 / 40.0];
+    const A4: [f64; 3] = [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0];
+    const A5: [f64; 4] = [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0];
+    const A6: [f64; 5] = [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0];
+    // 5th order weights; k2 has weight 0
+    const B: [f64; 6] = [35.0 / 384.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0, 0.0];
+    // difference between the 5th and the 4th order weights
+    const E: [f64; 6] = [
+        71.0 / 57600.0,
+        -71.0 / 16695.0,
+        71.0 / 1920.0,
+        -17253.0 / 339200.0,
+        22.0 / 525.0,
+        -1.0 / 40.0,
+    ];
+}
+
+impl Integrator for DormandPrince {
+    fn step(&self, p: &Params, s: &mut State, h: &mut f64) -> f64 {
+        let y = s.to_array();
+        let k1 = deriv(p, &y);
+        loop {
+            let dt = *h;
+            let a = (Self::A2, Self::A3, Self::A4, Self::A5, Self::A6);
+            let k2 = deriv(p, &combine(&y, dt, &[(a.0[0], &k1)]));
+            let k3 = deriv(p, &combine(&y, dt, &[(a.1[0], &k1), (a.1[1], &k2)]));
+            let k4 = deriv(p, &combine(&y, dt, &[(a.2[0], &k1), (a.2[1], &k2), (a.2[2], &k3)]));
+            let k5 = deriv(p, &combine(&y, dt, &[(a.3[0], &k1), (a.3[1], &k2), (a.3[2], &k3), (a.3[3], &k4)]));
+            let k6 = deriv(
+                p,
+                &combine(&y, dt, &[(a.4[0], &k1), (a.4[1], &k2), (a.4[2], &k3), (a.4[3], &k4), (a.4[4], &k5)]),
+            );
+            let b = Self::B;
+            let y5 = combine(&y, dt, &[(b[0], &k1), (b[1], &k3), (b[2], &k4), (b[3], &k5), (b[4], &k6)]);
+            let k7 = deriv(p, &y5);
+            let e = Self::E;
+            let err = combine(&[0.0; 4], dt, &[(e[0], &k1), (e[1], &k3), (e[2], &k4), (e[3], &k5), (e[4], &k6), (e[5], &k7)]);
+            let err = (0..4)
+                .map(|i| err[i].abs() / (self.atol + self.rtol * y[i].abs().max(y5[i].abs())))
+                .fold(0.0, f64::max);
+
+            let scale = if err == 0.0 {
+                Self::MAX_SCALE
+            }
+            else {
+                (Self::SAFETY * err.powf(-0.2)).clamp(Self::MIN_SCALE, Self::MAX_SCALE)
+            };
+            *h = (dt * scale).clamp(Self::MIN_H, Self::MAX_H);
+            if err <= 1.0 || dt <= Self::MIN_H {
+                *s = y5.into();
+                return dt;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IntegratorKind {
+    /// semi-implicit euler, first order (the original one)
+    #[default]
+    Euler,
+    /// classic runge-kutta, fourth order
+    Rk4,
+    /// velocity verlet, second order. not symplectic, the accelerations depend on the velocities
+    Verlet,
+    /// implicit midpoint on canonical momenta, second order, the symplectic one. needs --equations lagrange
+    Midpoint,
+    /// adaptive dormand-prince 5(4), step size controlled by atol and rtol
+    Rk45,
+}
+
+impl IntegratorKind {
+    /// `atol` and `rtol` are only used by the adaptive integrators
+    pub fn build(&self, atol: f64, rtol: f64) -> Box<dyn Integrator + Send + Sync> {
+        match self {
+            IntegratorKind::Euler => Box::new(SemiImplicitEuler),
+            IntegratorKind::Rk4 => Box::new(Rk4),
+            IntegratorKind::Verlet => Box::new(VelocityVerlet),
+            IntegratorKind::Midpoint => Box::new(ImplicitMidpoint),
+            IntegratorKind::Rk45 => Box::new(DormandPrince { atol, rtol }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use super::{accel, energy, flip1_energy, flip_energy, DormandPrince, Equations, Integrator, IntegratorKind};
+    use super::{Params, State, G};
+
+    const P: Params = Params { l1: 512.0, l2: 512.0, m1: 1.0, m2: 1.0, g: G, equations: Equations::Lagrange };
+    const S: State = State { theta1: 2.0, theta2: 1.0, dt1: 0.0, dt2: 0.0 };
+
+    fn run_with(p: &Params, kind: IntegratorKind, h: f64, t: f64) -> State {
+        let integrator = kind.build(1e-10, 1e-10);
+        let mut s = S;
+        for _ in 0..(t / h).round() as usize {
+            integrator.step(p, &mut s, &mut h.clone());
+        }
+        s
+    }
+
+    fn run(kind: IntegratorKind, h: f64, t: f64) -> State {
+        run_with(&P, kind, h, t)
+    }
+
+    #[test]
+    fn test_energy_is_conserved() {
+        let e0 = energy(&P, &S);
+        let e = energy(&P, &run(IntegratorKind::Rk4, 0.01, 100.0));
+        assert!(((e - e0) / e0).abs() < 1e-8, "{} {}", e0, e);
+        // symplectic: bounded error, no drift
+        let e = energy(&P, &run(IntegratorKind::Midpoint, 0.01, 100.0));
+        assert!(((e - e0) / e0).abs() < 1e-4, "{} {}", e0, e);
+
+        let heavy = Params { m1: 0.2, m2: 3.0, ..P };
+        let e0 = energy(&heavy, &S);
+        let e = energy(&heavy, &run_with(&heavy, IntegratorKind::Rk4, 0.001, 100.0));
+        assert!(((e - e0) / e0).abs() < 1e-8, "{} {}", e0, e);
+    }
+
+    #[test]
+    fn test_mass_scale() {
+        // scaling both masses by a power of two changes no bits of the motion,
+        // so unit masses give the same result as the old masses = lengths = 512
+        let old = Params { m1: 512.0, m2: 512.0, ..P };
+        let s = State { dt1: 0.3, dt2: -1.7, ..S };
+        assert_eq!(accel(&P, &s), accel(&old, &s));
+        let heavy = Params { m2: 2.0, ..P };
+        assert_ne!(accel(&P, &s), accel(&heavy, &s));
+    }
+
+    #[test]
+    fn test_baseline_equations() {
+        let baseline = Params { equations: Equations::Baseline, ..P };
+        // the same without dt1, the other way round with it
+        let s = State { dt1: 0.0, dt2: -1.7, ..S };
+        assert_eq!(accel(&P, &s), accel(&baseline, &s));
+        let s = State { dt1: 0.3, ..s };
+        let (l, b) = (accel(&P, &s), accel(&baseline, &s));
+        assert_eq!(l.1, b.1);
+        let term = 2.0 * (s.theta1 - s.theta2).sin() * 2.0 * s.dt1 * s.dt1 * P.l1 * (s.theta1 - s.theta2).cos();
+        let a = 3.0 - (2.0 * s.theta1 - 2.0 * s.theta2).cos();
+        assert!((b.0 - l.0 - term / (P.l1 * a)).abs() < 1e-12, "{:?} {:?}", l, b);
+        // and they do not keep the energy
+        let (rk4, mut s) = (IntegratorKind::Rk4.build(0.0, 0.0), State { dt1: 1.0, ..S });
+        let e0 = energy(&baseline, &s);
+        for _ in 0..10_000 {
+            rk4.step(&baseline, &mut s, &mut 0.01);
+        }
+        assert!(((energy(&baseline, &s) - e0) / e0).abs() > 1e-3);
+    }
+
+    #[test]
+    fn test_flip_energy() {
+        let up = State { theta1: 0.0, theta2: PI, dt1: 0.0, dt2: 0.0 };
+        assert!((energy(&P, &up) - flip_energy(&P)).abs() < 1e-9);
+        assert!(energy(&P, &S) > flip_energy(&P));
+        // still below the threshold after a kick, the second arm swings but never goes over
+        let low = State { theta1: 0.2, theta2: 2.5, dt1: 0.0, dt2: 0.05 };
+        assert!(energy(&P, &low) < flip_energy(&P));
+        let (rk4, mut s) = (IntegratorKind::Rk4.build(0.0, 0.0), low);
+        for _ in 0..30_000 {
+            rk4.step(&P, &mut s, &mut 0.01);
+            assert!(s.theta2.abs() < PI, "{:?}", s);
+        }
+        // the first arm up costs more than the second
+        let up1 = State { theta1: PI, theta2: 0.0, dt1: 0.0, dt2: 0.0 };
+        assert!((energy(&P, &up1) - flip1_energy(&P)).abs() < 1e-9);
+        assert!(flip1_energy(&P) > flip_energy(&P));
+    }
+
+    #[test]
+    fn test_integrators_converge() {
+        let exact = run(IntegratorKind::Rk4, 0.0005, 5.0);
+        for (kind, h, tol) in [
+            (IntegratorKind::Euler, 0.0005, 1e-2),
+            (IntegratorKind::Verlet, 0.001, 1e-4),
+            (IntegratorKind::Midpoint, 0.001, 1e-4),
+            (IntegratorKind::Rk4, 0.01, 1e-6),
+        ] {
+            let s = run(kind, h, 5.0);
+            let err = (s.theta1 - exact.theta1).abs().max((s.theta2 - exact.theta2).abs());
+            assert!(err < tol, "{:?}: {}", kind, err);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_step() {
+        let exact = run(IntegratorKind::Rk4, 0.0005, 5.0);
+        let rk45 = DormandPrince { atol: 1e-10, rtol: 1e-10 };
+        let (mut s, mut h, mut t, mut steps) = (S, 0.01_f64, 0.0, 0);
+        while t < 5.0 {
+            h = h.min(5.0 - t);
+            t += rk45.step(&P, &mut s, &mut h);
+            steps += 1;
+        }
+        let err = (s.theta1 - exact.theta1).abs().max((s.theta2 - exact.theta2).abs());
+        assert!(err < 1e-7, "{}", err);
+        // calm start, it should not need the fixed 0.01 to get there
+        assert!(steps < 500, "{}", steps);
+    }
+}
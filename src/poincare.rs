@@ -0,0 +1,170 @@
+use std::f64::consts::{PI, TAU};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use ggez::graphics::{self, Color, DrawMode, Rect, Text};
+use ggez::*;
+use glam::*;
+
+use crate::physics::{Integrator, Params, State};
+
+// physics steps per frame of the view
+const STEPS_FRAME: usize = 20_000;
+
+/// theta modulo TAU, in -PI..PI
+fn wrap(theta: f64) -> f64 {
+    theta - TAU * ((theta + PI) / TAU).floor()
+}
+
+/// the poincaré section of one pendulum: (theta2, dt2) every time theta1 goes up through 0 (modulo
+/// TAU). regular motion puts the points on closed curves, chaotic motion scatters them
+pub struct Section {
+    pub params: Params,
+    pub start:  State,
+    state:      State,
+    // size of the next step, changed by adaptive integrators
+    h:          f64,
+    // simulated time, seconds
+    pub t:      f64,
+    // theta2 in -PI..PI, dt2
+    pub points: Vec<DVec2>,
+}
+
+impl Section {
+    pub fn new(params: Params, start: State, h: f64) -> Self {
+        Section { params, start, state: start, h, t: 0.0, points: Vec::new() }
+    }
+
+    /// integrate `steps` more steps and collect the crossings
+    pub fn run(&mut self, integrator: &dyn Integrator, steps: usize) {
+        for _ in 0..steps {
+            let before = self.state;
+            self.t += integrator.step(&self.params, &mut self.state, &mut self.h);
+            let (a, b) = (before.theta1, self.state.theta1);
+            let level = TAU * (b / TAU).floor();
+            if !(b > a && a < level) {
+                continue;
+            }
+            // straight line between the two ends of the step
+            let s = (level - a) / (b - a);
+            let lerp = |x: f64, y: f64| x + s * (y - x);
+            if lerp(before.dt1, self.state.dt1) > 0.0 {
+                let theta2 = lerp(before.theta2, self.state.theta2);
+                self.points.push(dvec2(wrap(theta2), lerp(before.dt2, self.state.dt2)));
+            }
+        }
+    }
+
+    /// write the points as a .csv, one row per crossing
+    pub fn save_csv(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        writeln!(w, "theta2,dt2")?;
+        for p in &self.points {
+            writeln!(w, "{},{}", p.x, p.y)?;
+        }
+        w.flush()
+    }
+}
+
+/// the section of a clicked cell, growing while it is shown
+pub struct PoincareView {
+    section:    Section,
+    integrator: Box<dyn Integrator + Send + Sync>,
+    // seconds to integrate for
+    time:       f64,
+}
+
+impl PoincareView {
+    pub fn new(section: Section, integrator: Box<dyn Integrator + Send + Sync>, time: f64) -> Self {
+        PoincareView { section, integrator, time }
+    }
+
+    pub fn section(&self) -> &Section {
+        &self.section
+    }
+
+    pub fn update(&mut self) {
+        if self.section.t < self.time {
+            self.section.run(&*self.integrator, STEPS_FRAME);
+        }
+    }
+
+    /// draw the panel in the bottom right corner of a window of `dims` pixels, below the replay
+    pub fn draw(&self, ctx: &mut Context, dims: DVec2) -> GameResult<()> {
+        let side = dims.min_element() / 2.0;
+        let panel = Rect::new((dims.x - side) as f32, (dims.y - side) as f32, side as f32, side as f32);
+        let mb = &mut graphics::MeshBuilder::new();
+        mb.rectangle(DrawMode::fill(), panel, Color::new(0.0, 0.0, 0.0, 0.85))?;
+        mb.rectangle(DrawMode::stroke(1.0), panel, Color::WHITE)?;
+        // theta2 from -PI to PI across, dt2 up to the largest one up and down
+        let points = &self.section.points;
+        let speed = points.iter().map(|p| p.y.abs()).fold(0.0, f64::max).max(1e-9);
+        let center = dvec2(dims.x - side / 2.0, dims.y - side / 2.0);
+        let r = side * 0.45;
+        let color = Color::new(0.4, 0.8, 1.0, 1.0);
+        for p in points {
+            let q = center + dvec2(p.x / PI * r, -p.y / speed * r);
+            mb.rectangle(DrawMode::fill(), Rect::new(q.x as f32, q.y as f32, 1.5, 1.5), color)?;
+        }
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, (vec2(0.0, 0.0),))?;
+
+        let s = &self.section.start;
+        let text = Text::new(format!(
+            "poincare section at theta1 = 0 going up\nfrom theta1 = {:.4}  theta2 = {:.4}  dt1 = {:.4}  dt2 = {:.4}\n\
+             theta2 -pi .. pi, dt2 -{:.3} .. {:.3}\n{} points in {:.0} s\nC saves them",
+            s.theta1,
+            s.theta2,
+            s.dt1,
+            s.dt2,
+            speed,
+            speed,
+            points.len(),
+            self.section.t
+        ));
+        graphics::draw(ctx, &text, (vec2(panel.x + 8.0, panel.y + 8.0), Color::WHITE))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+    use std::f64::consts::PI;
+
+    use super::{wrap, Section};
+    use crate::pendulum::Config;
+    use crate::physics::{IntegratorKind, State};
+
+    #[test]
+    fn test_section() {
+        assert_eq!((wrap(0.5), wrap(-0.5)), (0.5, -0.5));
+        assert!((wrap(2.0 * PI + 0.5) - 0.5).abs() < 1e-12 && (wrap(3.5 * PI) + 0.5 * PI).abs() < 1e-12);
+
+        let config = Config::default();
+        let (params, _) = config.start(0.0, 0.0, 2048.0);
+        let rk4 = IntegratorKind::Rk4.build(0.0, 0.0);
+        // small swings: once per period, always near the bottom
+        let start = State { theta1: 0.0, theta2: 0.0, dt1: 0.01, dt2: 0.0 };
+        let mut small = Section::new(params, start, 0.01);
+        small.run(&*rk4, 100_000);
+        let period = 2.0 * PI / (params.g / params.l1).sqrt();
+        assert!(small.points.len() as f64 > small.t / period / 2.0, "{} in {} s", small.points.len(), small.t);
+        assert!(small.points.iter().all(|p| p.x.abs() < 0.1 && p.y.is_finite()));
+
+        // whirls: theta1 goes round and round, every turn is a crossing
+        let whirl = State { dt1: 2.0, ..start };
+        let mut fast = Section::new(params, whirl, 0.01);
+        fast.run(&*rk4, 3000);
+        assert_eq!(fast.points.len(), (fast.state.theta1 / (2.0 * PI)) as usize);
+        assert!(fast.points.len() > 1 && fast.points.iter().all(|p| p.x.abs() <= PI));
+
+        let path = temp_dir().join(format!("dpfrac-test-{}-poincare.csv", std::process::id()));
+        small.save_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(csv.lines().count(), small.points.len() + 1);
+        assert_eq!(csv.lines().next(), Some("theta2,dt2"));
+    }
+}
@@ -0,0 +1,369 @@
+use std::f64::consts::{PI, TAU};
+
+use angular_units::{Angle, Rad};
+use clap::ValueEnum;
+use ggez::graphics::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::lyapunov::LyapunovPolicy;
+use crate::pendulum::{flip_fraction, Arm, Config, Flip, FlipInterp, Motion, STEP_DELTA};
+use crate::physics::{flip1_energy, flip_energy, State};
+
+/// what stops a pendulum and how its cell is colored. shared by all the cells of a family, the
+/// per-pendulum state lives in `Motion`
+pub trait PendulumPolicy: Send + Sync {
+    /// called after every step of `m`, `before` is the state before it and `dt` its length. true stops
+    /// the pendulum, with `m.flip_t` set to the time of the event
+    fn stop(&self, m: &mut Motion, before: &State, dt: f64) -> bool;
+    /// the pendulum can never stop, so it is not run. checked before the start
+    fn never(&self, m: &Motion) -> bool;
+    /// color of the stopped cell
+    fn color(&self, m: &Motion) -> Color;
+    /// color of the arms of the running pendulum
+    fn pcolor(&self, m: &Motion) -> Color;
+    /// the stopped neighbors `a` and `b` differ enough to split both. `dive_diff` is the one of the config
+    fn differ(&self, a: &Motion, b: &Motion, dive_diff: f64) -> bool {
+        a.flip_t.min(b.flip_t) / a.flip_t.max(b.flip_t) < dive_diff
+    }
+}
+
+/// the stop condition of the cells
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyKind {
+    /// the second arm over the top
+    #[default]
+    Flip,
+    /// the first arm over the top
+    FirstArm,
+    /// whichever arm gets over the top first
+    EitherArm,
+    /// no flip, runs for lyap_time and is colored by how fast nearby starts drift apart
+    Lyapunov,
+}
+
+impl PolicyKind {
+    pub fn build(&self, config: &Config) -> Box<dyn PendulumPolicy> {
+        match self {
+            PolicyKind::Flip | PolicyKind::FirstArm | PolicyKind::EitherArm => Box::new(FlipPolicy::new(config)),
+            PolicyKind::Lyapunov => Box::new(LyapunovPolicy::new(config)),
+        }
+    }
+}
+
+/// what the color of a stopped cell shows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorBy {
+    /// the time of the flip
+    #[default]
+    Time,
+    /// which arm flipped last and which way, darker for the earlier flips
+    Arm,
+    /// the energy error of the integrator until the stop, expired cells too
+    Drift,
+}
+
+pub(crate) fn rgb(c: prisma::Rgb<f32>) -> Color {
+    (c.red(), c.green(), c.blue()).into()
+}
+
+/// the colors every policy shares: dark for the cells that never stop, gray for the expired ones
+pub(crate) fn gray(m: &Motion) -> Option<Color> {
+    if m.never {
+        Some(rgb(prisma::Rgb::new(0.2, 0.2, 0.3)))
+    }
+    else if m.expired {
+        Some(rgb(prisma::Rgb::new(0.7, 0.7, 0.7)))
+    }
+    else {
+        None
+    }
+}
+
+/// the relative energy drift of a cell on a log scale, 0 below 1e-6 to 1 at 1 and above
+pub(crate) fn drift_shade(m: &Motion) -> f64 {
+    ((m.drift.abs().log10() + 6.0) / 6.0).clamp(0.0, 1.0)
+}
+
+/// the relative energy drift of a cell that ran, green below 1e-6 to red at 1 and above (log scale)
+pub(crate) fn drift_color(m: &Motion) -> Color {
+    let x = drift_shade(m);
+    rgb(prisma::Hsv::new(Rad::new(2.1 * (1.0 - x)), 1.0, 1.0).into())
+}
+
+/// the arms of a running pendulum, the hue follows theta2
+pub(crate) fn arm_color(m: &Motion) -> Color {
+    let mut p = m.state.theta2.abs();
+    while p > TAU {
+        p -= TAU
+    }
+    rgb(prisma::Hsv::new(Rad::new(p), 0.5, 1.0).into())
+}
+
+/// the top (an odd multiple of PI) an angle went over from `a` to `b`
+fn over_top(a: f64, b: f64) -> Option<f64> {
+    let (ka, kb) = (((a + PI) / TAU).floor(), ((b + PI) / TAU).floor());
+    (ka != kb).then(|| ka.max(kb) * TAU - PI)
+}
+
+/// stops at the Nth time an arm goes over the top. the first flip of the second arm is the classic
+/// picture
+pub struct FlipPolicy {
+    // watched arms
+    first:      bool,
+    second:     bool,
+    count:      u32,
+    interp:     FlipInterp,
+    color_by:   ColorBy,
+    color_step: usize,
+    color_mod:  usize,
+}
+
+impl FlipPolicy {
+    pub fn new(config: &Config) -> Self {
+        FlipPolicy {
+            first:      config.policy != PolicyKind::Flip,
+            second:     config.policy != PolicyKind::FirstArm,
+            count:      config.flip_count,
+            interp:     config.flip_interp,
+            color_by:   config.color_by,
+            color_step: (config.color_step * STEP_DELTA) as usize,
+            color_mod:  config.color_mod,
+        }
+    }
+
+    /// the first flip of the second arm, the test the classic pictures were made with. `prev` is
+    /// theta2 before the step. returns the fraction of the step at the flip
+    fn classic(&self, prev: f64, before: &State, after: &State, dt: f64) -> Option<(f64, Flip)> {
+        let (theta2, dt2) = (after.theta2, after.dt2);
+        // dt2 > 0 -> clockwise; dt2 < 0 - counter-cw
+        // -3.13 -> -3.15;  dt-
+        // 3.15 -> 3.13     dt-
+        // -3.15 -> -3.13;  dt+
+        // 3.13 -> 3.15;    dt+
+        let (down, up) = (theta2 < -PI && prev > -PI, theta2 > PI && prev < PI);
+        // back over a top the pendulum started beyond, with dt2 the way it went. the baseline had only the first
+        // one, each case needs its mirror image for `Config::mirrored`. inside -PI .. PI the semi-implicit euler
+        // never gets here, theta2 moves the way dt2 points after its step
+        let (back_down, back_up) = (theta2 < PI && prev > PI, theta2 > -PI && prev < -PI);
+
+        if !(prev.is_finite()
+            && ((dt2 > 0.0 && (down || up || back_up)) || (dt2 < 0.0 && (down || up || back_down))))
+        {
+            return None;
+        }
+        let level = if down || back_up { -PI } else { PI };
+        let s = flip_fraction(self.interp, level, (prev, before.dt2), (theta2, dt2), dt);
+        Some((s, Flip { arm: Arm::Second, clockwise: dt2 > 0.0 }))
+    }
+
+    /// the earliest flip of a watched arm in the step from `before` to `after`
+    fn any(&self, before: &State, after: &State, dt: f64) -> Option<(f64, Flip)> {
+        let arms = [
+            (Arm::First, self.first, (before.theta1, before.dt1), (after.theta1, after.dt1)),
+            (Arm::Second, self.second, (before.theta2, before.dt2), (after.theta2, after.dt2)),
+        ];
+        let mut res: Option<(f64, Flip)> = None;
+        for (arm, watched, a, b) in arms {
+            let Some(level) = over_top(a.0, b.0).filter(|_| watched)
+            else {
+                continue;
+            };
+            let s = flip_fraction(self.interp, level, a, b, dt);
+            if res.is_none_or(|(first, _)| s < first) {
+                res = Some((s, Flip { arm, clockwise: b.0 > a.0 }));
+            }
+        }
+        res
+    }
+
+    /// the flip time in whole steps, as the baseline counted it
+    fn ticks(m: &Motion) -> usize {
+        (m.flip_t / STEP_DELTA).round() as usize
+    }
+
+    /// the hue of a flip
+    fn hue(flip: Flip) -> f64 {
+        match (flip.arm, flip.clockwise) {
+            (Arm::First, true) => 0.0,
+            (Arm::First, false) => 1.0,
+            (Arm::Second, true) => 4.0,
+            (Arm::Second, false) => 3.0,
+        }
+    }
+}
+
+impl PendulumPolicy for FlipPolicy {
+    fn stop(&self, m: &mut Motion, before: &State, dt: f64) -> bool {
+        let prev = m.prev;
+        m.prev = m.state.theta2;
+        let flip = if self.first || self.count > 1 {
+            self.any(before, &m.state, dt)
+        }
+        else {
+            self.classic(prev, before, &m.state, dt)
+        };
+        let Some((s, flip)) = flip
+        else {
+            return false;
+        };
+        m.flips += 1;
+        m.flip = Some(flip);
+        if m.flips < self.count {
+            return false;
+        }
+        m.flip_t = m.t - (1.0 - s) * dt;
+        true
+    }
+
+    fn never(&self, m: &Motion) -> bool {
+        let (first, second) = (flip1_energy(&m.params), flip_energy(&m.params));
+        match (self.first, self.second) {
+            (true, true) => m.below(first.min(second)),
+            (true, false) => m.below(first),
+            _ => m.below(second),
+        }
+    }
+
+    fn color(&self, m: &Motion) -> Color {
+        if self.color_by == ColorBy::Drift && !m.never {
+            return drift_color(m);
+        }
+        gray(m).unwrap_or_else(|| {
+            let p = if self.interp == FlipInterp::None {
+                // a whole number of steps, in integers as the baseline did
+                (Self::ticks(m) * self.color_step % self.color_mod) as f64 / (self.color_mod as f64) * TAU
+            }
+            else {
+                (m.flip_t / STEP_DELTA * self.color_step as f64) % (self.color_mod as f64) / (self.color_mod as f64) * TAU
+            };
+            match (self.color_by, m.flip) {
+                (ColorBy::Arm, Some(flip)) => {
+                    rgb(prisma::Hsv::new(Rad::new(Self::hue(flip)), 0.8, (0.4 + 0.6 * p / TAU) as f32).into())
+                }
+                _ => rgb(prisma::Hsv::new(Rad::new(p), 1.0, 1.0).into()),
+            }
+        })
+    }
+
+    fn pcolor(&self, m: &Motion) -> Color {
+        arm_color(m)
+    }
+
+    fn differ(&self, a: &Motion, b: &Motion, dive_diff: f64) -> bool {
+        if self.color_by == ColorBy::Drift && !a.never && !b.never {
+            return (drift_shade(a) - drift_shade(b)).abs() > 1.0 - dive_diff;
+        }
+        let arm = |m: &Motion| m.flip.map(|f| (f.arm, f.clockwise));
+        let ratio = if self.interp == FlipInterp::None {
+            // the ratio of the step counts, a sum of steps can land on the other side of dive_diff
+            let (a, b) = (Self::ticks(a), Self::ticks(b));
+            a.min(b) as f64 / a.max(b) as f64
+        }
+        else {
+            a.flip_t.min(b.flip_t) / a.flip_t.max(b.flip_t)
+        };
+        ratio < dive_diff
+            || self.color_by == ColorBy::Arm && arm(a) != arm(b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::*;
+
+    use std::f64::consts::PI;
+
+    use super::{over_top, ColorBy, FlipPolicy, LyapunovPolicy, PendulumPolicy, PolicyKind};
+    use crate::pendulum::{Arm, Config, DoublePendulum, Flip, FlipInterp, Motion};
+    use crate::physics::State;
+
+    #[test]
+    fn test_flip() {
+        let policy = FlipPolicy::new(&Config { flip_interp: FlipInterp::Hermite, ..Default::default() });
+        let mut m = DoublePendulum::new2(dvec2(50.0, 50.0), dvec2(100.0, 100.0), 1.0, &Config::default()).motion;
+        let before = m.state;
+        (m.state.theta2, m.state.dt2, m.t) = (3.2, 1.0, 1.0);
+        // no flip on the first step
+        assert!(!policy.stop(&mut m, &before, 0.1));
+        assert_eq!(m.prev, 3.2);
+
+        let before = m.state;
+        (m.prev, m.state.theta2) = (3.1, 3.2);
+        assert!(policy.stop(&mut m, &before, 0.1));
+        assert!(m.flip_t > 0.9 && m.flip_t < 1.0, "{}", m.flip_t);
+        assert_eq!(m.flip, Some(Flip { arm: Arm::Second, clockwise: true }));
+        // up through PI while dt2 says down is a flip, as its mirror image down through -PI while dt2 says up is.
+        // and back down over the top after starting beyond it, as back up over -PI
+        for (prev, theta2, dt2) in [(3.1, 3.2, -1.0), (-3.1, -3.2, 1.0), (3.3, 3.1, -1.0), (-3.3, -3.1, 1.0)] {
+            let mut m = Motion { prev, state: State { theta2, dt2, ..m.state }, ..m };
+            assert!(policy.stop(&mut m, &before, 0.1), "{} {}", prev, theta2);
+            assert_eq!(m.flip, Some(Flip { arm: Arm::Second, clockwise: dt2 > 0.0 }));
+        }
+        (m.prev, m.state.theta2, m.state.dt2) = (3.0, 3.1, 1.0);
+        assert!(!policy.stop(&mut m, &before, 0.1));
+        let expired = Motion { expired: true, ..m };
+        assert_ne!(policy.color(&m), policy.color(&expired));
+
+        // by default the flip is at the end of the step, and the color counts whole steps
+        let policy = FlipPolicy::new(&Config::default());
+        let (before, t) = (m.state, m.t);
+        (m.prev, m.state.theta2, m.state.dt2) = (3.1, 3.2, 1.0);
+        assert!(policy.stop(&mut m, &before, 0.1) && m.flip_t == t);
+        let colors = [1000.0, 1000.0 + 1e-12, 7000.0].map(|ticks| policy.color(&Motion { flip_t: ticks * 0.01, ..m }));
+        assert!(colors[0] == colors[1] && colors[0] == colors[2]);
+    }
+
+    #[test]
+    fn test_arms() {
+        assert_eq!(over_top(-3.0, -3.3), Some(-PI));
+        assert_eq!(over_top(3.0 * PI - 0.1, 3.0 * PI + 0.1), Some(3.0 * PI));
+        assert_eq!(over_top(3.0, -3.0), None);
+
+        let config = Config {
+            policy: PolicyKind::EitherArm,
+            flip_count: 2,
+            color_by: ColorBy::Arm,
+            flip_interp: FlipInterp::Hermite,
+            ..Default::default()
+        };
+        let policy = FlipPolicy::new(&config);
+        let mut m = DoublePendulum::new2(dvec2(50.0, 50.0), dvec2(100.0, 100.0), 1.0, &config).motion;
+        let step = |m: &mut Motion, before: State, after: State| {
+            (m.state, m.t) = (after, m.t + 0.1);
+            policy.stop(m, &before, 0.1)
+        };
+        let s = State { theta1: 3.1, theta2: -3.1, dt1: 1.0, dt2: -1.0 };
+        // the second arm first, counter-clockwise, not yet the second flip
+        assert!(!step(&mut m, s, State { theta1: 3.1, theta2: -3.2, ..s }));
+        assert_eq!((m.flips, m.flip), (1, Some(Flip { arm: Arm::Second, clockwise: false })));
+        let first = m;
+        // both arms in one step, the first arm gets there earlier
+        assert!(step(&mut m, s, State { theta1: 3.2, theta2: -3.15, ..s }));
+        assert_eq!((m.flips, m.flip), (2, Some(Flip { arm: Arm::First, clockwise: true })));
+        assert!(m.flip_t > 0.1 && m.flip_t < 0.2, "{}", m.flip_t);
+        assert!(policy.differ(&first, &m, 0.0));
+
+        // enough energy for the second arm, not for the first
+        let first_arm = FlipPolicy::new(&Config { policy: PolicyKind::FirstArm, ..Default::default() });
+        let m = Motion { state: State { theta1: 0.3, theta2: 3.0, dt1: 0.0, dt2: 0.0 }, ..m };
+        assert!(first_arm.never(&m) && !policy.never(&m));
+    }
+
+    #[test]
+    fn test_drift_differ() {
+        let config = Config { color_by: ColorBy::Drift, ..Default::default() };
+        let m = DoublePendulum::new2(dvec2(50.0, 50.0), dvec2(100.0, 100.0), 1.0, &config).motion;
+        let (a, b) = (Motion { flip_t: 2.0, drift: 1e-9, ..m }, Motion { flip_t: 2.0, drift: 1e-2, ..m });
+        let later = Motion { flip_t: 20.0, ..a };
+        let policies: [Box<dyn PendulumPolicy>; 2] =
+            [Box::new(FlipPolicy::new(&config)), Box::new(LyapunovPolicy::new(&config))];
+        // only the drift is drawn, so only the drift splits
+        for policy in &policies {
+            assert!(policy.differ(&a, &b, 0.9) && !policy.differ(&a, &later, 0.9));
+        }
+        let time = FlipPolicy::new(&Config::default());
+        assert!(!time.differ(&a, &b, 0.9) && time.differ(&a, &later, 0.9));
+    }
+}
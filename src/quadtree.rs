@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::pendulum::PendulumFamily;
+use crate::physics::Quantity;
+use crate::region::Region;
+
+/// one cell of the adaptive quadtree. positions and sizes in the quantities of the axes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CellRecord {
+    pub id:      usize,
+    // the root is its own parent
+    pub parent:  usize,
+    // 0 for the root, the childs of a cell are one deeper
+    pub depth:   u32,
+    pub x:       f64,
+    pub y:       f64,
+    // half of the sides
+    pub half_x:  f64,
+    pub half_y:  f64,
+    pub stopped: bool,
+    pub expired: bool,
+    pub never:   bool,
+    pub steps:   usize,
+    // seconds, the time it ran for the expired ones
+    pub flip_t:  f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Tree {
+    x_axis: Quantity,
+    y_axis: Quantity,
+    region: Region,
+    // window size, pixels
+    width:  f64,
+    height: f64,
+    cells:  Vec<CellRecord>,
+}
+
+/// every cell of the family, by id
+pub fn records(family: &PendulumFamily) -> Vec<CellRecord> {
+    let (cells, dims, region) = (&family.cells, family.dims(), family.config().region);
+    (0..cells.len())
+        .map(|id| {
+            let (p, half, m) = (cells.p[id], cells.half(id), &cells.motion[id]);
+            let v = region.at(p.x / dims.x, p.y / dims.y);
+            CellRecord {
+                id,
+                parent: cells.parent[id],
+                depth: (-cells.scale[id].log2()).round() as u32,
+                x: v.x,
+                y: v.y,
+                half_x: half.x / dims.x * region.width(),
+                half_y: half.y / dims.y * region.height(),
+                stopped: m.stopped,
+                expired: m.expired,
+                never: m.never,
+                steps: m.steps,
+                flip_t: m.flip_t,
+            }
+        })
+        .collect()
+}
+
+/// accepts the paths `save` can write, for the command line
+pub fn check_path(s: &str) -> Result<PathBuf, String> {
+    match Path::new(s).extension().and_then(|e| e.to_str()) {
+        Some("csv") | Some("json") => Ok(s.into()),
+        _ => Err("expected a .csv or .json file".into()),
+    }
+}
+
+/// write all the cells to a .csv (one row per cell) or a .json (with the axes and the region)
+pub fn save(path: &Path, family: &PendulumFamily) -> io::Result<()> {
+    let cells = records(family);
+    let mut w = BufWriter::new(File::create(path)?);
+    if path.extension().and_then(|e| e.to_str()) == Some("json") {
+        let config = family.config();
+        let dims = family.dims();
+        let tree = Tree {
+            x_axis: config.x_axis,
+            y_axis: config.y_axis,
+            region: config.region,
+            width: dims.x,
+            height: dims.y,
+            cells,
+        };
+        serde_json::to_writer(&mut w, &tree)?;
+    }
+    else {
+        writeln!(w, "id,parent,depth,x,y,half_x,half_y,stopped,expired,never,steps,flip_t")?;
+        for c in &cells {
+            writeln!(
+                w,
+                "{},{},{},{},{},{},{},{},{},{},{},{}",
+                c.id, c.parent, c.depth, c.x, c.y, c.half_x, c.half_y, c.stopped, c.expired, c.never, c.steps, c.flip_t
+            )?;
+        }
+    }
+    w.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+
+    use glam::*;
+
+    use super::{check_path, records, save, Tree};
+    use crate::pendulum::{run_all, small_config, Config, PendulumFamily};
+    use crate::region::Region;
+
+    #[test]
+    fn test_records() {
+        let config = Config { region: Region::new(0.0, 4.0, 0.0, 2.0), ..small_config() };
+        let mut family = PendulumFamily::new(config, dvec2(128.0, 64.0));
+        family.init();
+        run_all(&mut family);
+        let cells = records(&family);
+        assert_eq!(cells.len(), family.cells.len());
+        let root = &cells[0];
+        assert_eq!((root.parent, root.depth, root.x, root.y, root.half_x, root.half_y), (0, 0, 2.0, 1.0, 2.0, 1.0));
+        let child = &cells[family.cells.childs[0][0]];
+        assert_eq!((child.parent, child.depth, child.half_x), (0, 1, 1.0));
+        assert!(cells.iter().all(|c| c.stopped && c.depth == cells[c.parent].depth + (c.id != 0) as u32));
+
+        assert!(check_path("a.csv").is_ok() && check_path("a.json").is_ok() && check_path("a.txt").is_err());
+        let path = temp_dir().join(format!("dpfrac-test-{}.csv", std::process::id()));
+        save(&path, &family).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        save(&path.with_extension("json"), &family).unwrap();
+        let tree: Tree = serde_json::from_str(&std::fs::read_to_string(path.with_extension("json")).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("json")).unwrap();
+        assert_eq!(csv.lines().count(), cells.len() + 1);
+        assert!(csv.lines().nth(1).unwrap().starts_with("0,0,0,2,1,2,1,true,"));
+        assert_eq!(tree.cells, cells);
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use ggez::graphics::{Color, Rect};
+use ggez::GameResult;
+
+/// the pixels (x0, y0) .. (x1, y1) whose centers lie inside `rect`, clipped to the image
+pub(crate) fn pixel_span(rect: Rect, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let x0 = ((rect.x - 0.5).ceil().max(0.0) as u32).min(width);
+    let y0 = ((rect.y - 0.5).ceil().max(0.0) as u32).min(height);
+    let x1 = ((rect.x + rect.w - 0.5).ceil().max(0.0) as u32).min(width);
+    let y1 = ((rect.y + rect.h - 0.5).ceil().max(0.0) as u32).min(height);
+    (x0, y0, x1, y1)
+}
+
+/// in-memory RGBA image, used instead of the window canvas in headless mode. the colors are written
+/// as they are, the window goes through an srgb framebuffer and can round a channel by one
+pub struct Raster {
+    pub width:  u32,
+    pub height: u32,
+    data:       Vec<u8>,
+}
+
+impl Raster {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut data = vec![0; (width * height * 4) as usize];
+        // opaque black, same as the empty window
+        for px in data.chunks_exact_mut(4) {
+            px[3] = 255;
+        }
+        Raster { width, height, data }
+    }
+
+    /// fill every pixel whose center lies inside `rect`, like the gpu does for the mesh
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let (x0, y0, x1, y1) = pixel_span(rect, self.width, self.height);
+        let (r, g, b, a) = color.to_rgba();
+        for y in y0..y1 {
+            let row = (y * self.width) as usize * 4;
+            for px in self.data[row + x0 as usize * 4..row + x1 as usize * 4].chunks_exact_mut(4) {
+                px.copy_from_slice(&[r, g, b, a]);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> GameResult<()> {
+        image::save_buffer(path, &self.data, self.width, self.height, image::ColorType::Rgba8)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ggez::graphics::{Color, Rect};
+
+    use super::Raster;
+
+    #[test]
+    fn test_fill_rect() {
+        let mut img = Raster::new(8, 8);
+        img.fill_rect(Rect::new(2.0, 2.0, 4.0, 4.0), Color::WHITE);
+        assert_eq!(img.pixel(2, 2), [255, 255, 255, 255]);
+        assert_eq!(img.pixel(5, 5), [255, 255, 255, 255]);
+        assert_eq!(img.pixel(6, 5), [0, 0, 0, 255]);
+        assert_eq!(img.pixel(1, 2), [0, 0, 0, 255]);
+        // clipped to the image
+        img.fill_rect(Rect::new(-4.0, 6.0, 20.0, 20.0), Color::WHITE);
+        assert_eq!(img.pixel(0, 7), [255, 255, 255, 255]);
+
+        // a center on the left or top edge is inside, on the right or bottom edge it is not
+        let mut img = Raster::new(8, 8);
+        img.fill_rect(Rect::new(1.5, 1.5, 2.0, 2.0), Color::WHITE);
+        assert_eq!((img.pixel(1, 1), img.pixel(2, 2)), ([255, 255, 255, 255], [255, 255, 255, 255]));
+        assert_eq!((img.pixel(3, 2), img.pixel(2, 3)), ([0, 0, 0, 255], [0, 0, 0, 255]));
+    }
+}
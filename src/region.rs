@@ -0,0 +1,100 @@
+use std::f64::consts::{PI, TAU};
+
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+/// rectangle of the parameter plane shown in the window. x grows to the right, y grows down
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub xmin: f64,
+    pub xmax: f64,
+    pub ymin: f64,
+    pub ymax: f64,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region::new(0.0, TAU, 0.0, PI)
+    }
+}
+
+impl Region {
+    pub fn new(xmin: f64, xmax: f64, ymin: f64, ymax: f64) -> Self {
+        Region { xmin, xmax, ymin, ymax }
+    }
+
+    pub fn width(&self) -> f64 {
+        self.xmax - self.xmin
+    }
+
+    pub fn height(&self) -> f64 {
+        self.ymax - self.ymin
+    }
+
+    pub fn center(&self) -> DVec2 {
+        dvec2((self.xmin + self.xmax) / 2.0, (self.ymin + self.ymax) / 2.0)
+    }
+
+    /// the point at the fractions (fx, fy) of the width and the height, (0, 0) is (xmin, ymin)
+    pub fn at(&self, fx: f64, fy: f64) -> DVec2 {
+        dvec2(self.xmin + fx * self.width(), self.ymin + fy * self.height())
+    }
+
+    /// the box between the fractions `f0` and `f1` (corners in any order)
+    pub fn sub(&self, f0: DVec2, f1: DVec2) -> Region {
+        let (a, b) = (self.at(f0.x, f0.y), self.at(f1.x, f1.y));
+        Region::new(a.x.min(b.x), a.x.max(b.x), a.y.min(b.y), a.y.max(b.y))
+    }
+
+    /// `sub` of the box between `f0` and `f1` widened by `fit`, with the aspect of the whole region
+    pub fn sub_fit(&self, f0: DVec2, f1: DVec2) -> Region {
+        let (f0, f1) = fit(f0, f1);
+        self.sub(f0, f1)
+    }
+
+    /// `factor` times wider and higher around the same center
+    pub fn scaled(&self, factor: f64) -> Region {
+        let c = self.center();
+        let (w, h) = (self.width() / 2.0 * factor, self.height() / 2.0 * factor);
+        Region::new(c.x - w, c.x + w, c.y - h, c.y + h)
+    }
+}
+
+/// the box between the fractions `f0` and `f1` widened around its center to the same fraction of the width and the
+/// height, i.e. to the aspect of the window. returns the top left and the bottom right corners
+pub fn fit(f0: DVec2, f1: DVec2) -> (DVec2, DVec2) {
+    let center = (f0 + f1) / 2.0;
+    let half = DVec2::splat((f1 - f0).abs().max_element() / 2.0);
+    (center - half, center + half)
+}
+
+#[cfg(test)]
+mod test {
+    use glam::*;
+
+    use super::{fit, Region};
+
+    #[test]
+    fn test_at() {
+        let r = Region::new(1.0, 3.0, -1.0, 0.0);
+        assert_eq!(r.at(0.0, 0.0), dvec2(1.0, -1.0));
+        assert_eq!(r.at(1.0, 1.0), dvec2(3.0, 0.0));
+        assert_eq!(r.at(0.5, 0.5), r.center());
+        assert_eq!((r.width(), r.height()), (2.0, 1.0));
+    }
+
+    #[test]
+    fn test_zoom() {
+        let r = Region::new(0.0, 4.0, 0.0, 2.0);
+        let z = r.sub(dvec2(0.75, 1.0), dvec2(0.25, 0.5));
+        assert_eq!(z, Region::new(1.0, 3.0, 1.0, 2.0));
+        assert_eq!(z.scaled(2.0), Region::new(0.0, 4.0, 0.5, 2.5));
+        assert_eq!(z.scaled(2.0).center(), z.center());
+        // a flat box keeps its width and gets as high as the region is, relative to it
+        assert_eq!(fit(dvec2(0.75, 0.5), dvec2(0.25, 0.625)), (dvec2(0.25, 0.3125), dvec2(0.75, 0.8125)));
+        let f = r.sub_fit(dvec2(0.25, 0.5), dvec2(0.75, 0.625));
+        assert_eq!(f, Region::new(1.0, 3.0, 0.625, 1.625));
+        assert_eq!(f.width() / f.height(), r.width() / r.height());
+    }
+}